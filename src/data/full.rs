@@ -4,8 +4,12 @@ use serde::Serialize;
 use std::collections::{HashMap, HashSet};
 
 /// A data layer based on the Drogue IoT channel/feature model, sending full updates.
+///
+/// Updates marked as `transient` (e.g. command responses) are sent on their own, without
+/// becoming part of the channel state.
 pub struct FullFeatureDataLayer {
     channels: HashMap<String, Channel>,
+    metadata: Metadata,
 }
 
 #[derive(Clone, Debug, Serialize)]
@@ -14,9 +18,10 @@ struct Channel {
 }
 
 impl FullFeatureDataLayer {
-    pub fn new() -> Self {
+    pub fn new(metadata: MetadataMode) -> Self {
         Self {
             channels: Default::default(),
+            metadata: Metadata::new(metadata),
         }
     }
 }
@@ -27,6 +32,7 @@ impl DataLayer for FullFeatureDataLayer {
         I: Iterator<Item = Update>,
     {
         let mut channels = HashSet::new();
+        let mut transient = vec![];
 
         for update in updates {
             let feature = update
@@ -37,23 +43,45 @@ impl DataLayer for FullFeatureDataLayer {
                 .map(|s| s.to_string());
 
             if let Some(feature) = feature {
+                let value = self.metadata.apply(
+                    &update.channel,
+                    &feature,
+                    update.value,
+                    update.extensions.get("metadata"),
+                );
+
+                if update.extensions.get("transient") == Some(&Value::Bool(true)) {
+                    transient.push(mqtt::Event {
+                        channel: update.channel,
+                        payload: json!({
+                            "features": {
+                                feature: value,
+                            }
+                        }),
+                    });
+                    continue;
+                }
+
                 let channel = update.channel.clone();
                 channels.insert(channel.clone());
 
                 match self.channels.entry(channel) {
                     hash_map::Entry::Vacant(entry) => {
                         let mut features = HashMap::new();
-                        features.insert(feature, update.value);
+                        features.insert(feature, value);
                         entry.insert(Channel { features });
                     }
                     hash_map::Entry::Occupied(mut entry) => {
-                        entry.get_mut().features.insert(feature, update.value);
+                        entry.get_mut().features.insert(feature, value);
                     }
                 }
             }
         }
 
-        let result = channels
+        // metadata goes first, so that it is known when receiving the values
+        let mut result = self.metadata.events();
+
+        let values = channels
             .into_iter()
             .filter_map(|c| self.channels.get(&c).zip(Some(c)))
             .map(|(payload, channel)| {
@@ -63,7 +91,45 @@ impl DataLayer for FullFeatureDataLayer {
                 })
             })
             .collect::<Result<Vec<_>, _>>()?;
+        result.extend(values);
+        result.extend(transient);
 
         Ok(result)
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_transient() {
+        let mut layer = FullFeatureDataLayer::new(MetadataMode::Inline);
+
+        let events = layer
+            .update(vec![Update::new(["a", "state"], "c", json!({"value": 1}))].into_iter())
+            .unwrap();
+        assert_eq!(events.len(), 1);
+
+        let mut response = Update::new(["a", "read"], "c", json!({"status": "Good"}));
+        response
+            .extensions
+            .insert("transient".to_string(), true.into());
+        let events = layer.update(vec![response].into_iter()).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            events[0].payload,
+            json!({"features": {"read": {"status": "Good"}}})
+        );
+
+        // the response is not part of the channel state
+        let events = layer
+            .update(vec![Update::new(["a", "state"], "c", json!({"value": 2}))].into_iter())
+            .unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(
+            events[0].payload,
+            json!({"features": {"state": {"value": 2}}})
+        );
+    }
+}
@@ -0,0 +1,134 @@
+use crate::mqtt;
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+/// How metadata of features, provided by the `metadata` extension of an update, is sent.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Deserialize)]
+pub enum MetadataMode {
+    /// Add the metadata to the value of the feature, with every message.
+    #[serde(alias = "inline")]
+    Inline,
+    /// Send the metadata of a channel as separate message, whenever it changes.
+    #[serde(alias = "separate")]
+    Separate,
+}
+
+impl Default for MetadataMode {
+    fn default() -> Self {
+        Self::Inline
+    }
+}
+
+/// Tracking of feature metadata.
+pub struct Metadata {
+    mode: MetadataMode,
+    /// Last sent metadata, by channel and feature
+    sent: HashMap<String, HashMap<String, Value>>,
+    /// Changed metadata, by channel, not yet sent
+    changed: HashMap<String, Map<String, Value>>,
+}
+
+impl Metadata {
+    pub fn new(mode: MetadataMode) -> Self {
+        Self {
+            mode,
+            sent: Default::default(),
+            changed: Default::default(),
+        }
+    }
+
+    /// Apply the metadata of a feature, returning the value to send.
+    pub fn apply(
+        &mut self,
+        channel: &str,
+        feature: &str,
+        mut value: Value,
+        metadata: Option<&Value>,
+    ) -> Value {
+        let metadata = match metadata {
+            Some(metadata) => metadata,
+            None => return value,
+        };
+
+        match self.mode {
+            MetadataMode::Inline => {
+                if let Value::Object(value) = &mut value {
+                    value.insert("metadata".to_string(), metadata.clone());
+                }
+            }
+            MetadataMode::Separate => {
+                let sent = self.sent.entry(channel.to_string()).or_default();
+                if sent.get(feature) != Some(metadata) {
+                    sent.insert(feature.to_string(), metadata.clone());
+                    self.changed
+                        .entry(channel.to_string())
+                        .or_default()
+                        .insert(feature.to_string(), metadata.clone());
+                }
+            }
+        }
+
+        value
+    }
+
+    /// Take the events for metadata which changed since the last call.
+    pub fn events(&mut self) -> Vec<mqtt::Event> {
+        self.changed
+            .drain()
+            .map(|(channel, features)| mqtt::Event {
+                channel,
+                payload: json!({
+                    "metadata": features,
+                }),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_inline() {
+        let mut metadata = Metadata::new(MetadataMode::Inline);
+        let units = json!({"displayName": "rpm"});
+
+        assert_eq!(
+            metadata.apply("c", "f", json!({"value": 1}), Some(&units)),
+            json!({"value": 1, "metadata": {"displayName": "rpm"}})
+        );
+        assert_eq!(
+            metadata.apply("c", "f", json!({"value": 1}), None),
+            json!({"value": 1})
+        );
+        assert!(metadata.events().is_empty());
+    }
+
+    #[test]
+    fn test_separate() {
+        let mut metadata = Metadata::new(MetadataMode::Separate);
+        let units = json!({"displayName": "rpm"});
+
+        assert_eq!(
+            metadata.apply("c", "f", json!({"value": 1}), Some(&units)),
+            json!({"value": 1})
+        );
+        let events = metadata.events();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].channel, "c");
+        assert_eq!(
+            events[0].payload,
+            json!({"metadata": {"f": {"displayName": "rpm"}}})
+        );
+
+        // unchanged
+        metadata.apply("c", "f", json!({"value": 2}), Some(&units));
+        assert!(metadata.events().is_empty());
+
+        // changed
+        metadata.apply("c", "f", json!({"value": 3}), Some(&json!({})));
+        assert_eq!(metadata.events().len(), 1);
+    }
+}
@@ -1,8 +1,11 @@
 mod full;
+mod metadata;
 
 pub use full::*;
+pub use metadata::MetadataMode;
 
 use crate::{middleware::Update, mqtt};
+use metadata::Metadata;
 use serde_json::{json, Value};
 use std::collections::{hash_map, HashMap};
 use thiserror::Error;
@@ -21,11 +24,15 @@ pub trait DataLayer {
 }
 
 /// A data layer based on the Drogue IoT channel/feature model.
-pub struct FeatureDataLayer {}
+pub struct FeatureDataLayer {
+    metadata: Metadata,
+}
 
 impl FeatureDataLayer {
-    pub fn new() -> Self {
-        Self {}
+    pub fn new(metadata: MetadataMode) -> Self {
+        Self {
+            metadata: Metadata::new(metadata),
+        }
     }
 }
 
@@ -45,26 +52,36 @@ impl DataLayer for FeatureDataLayer {
                 .map(|s| s.to_string());
 
             if let Some(feature) = feature {
+                let value = self.metadata.apply(
+                    &update.channel,
+                    &feature,
+                    update.value,
+                    update.extensions.get("metadata"),
+                );
                 match compacted.entry(update.channel.clone()) {
                     hash_map::Entry::Vacant(entry) => {
                         entry.insert(mqtt::Event {
                             channel: update.channel,
                             payload: json!({
                                 "features": {
-                                    feature: update.value,
+                                    feature: value,
                                 }
                             }),
                         });
                     }
                     hash_map::Entry::Occupied(mut entry) => {
                         if let Value::Object(features) = &mut entry.get_mut().payload["features"] {
-                            features.insert(feature, update.value);
+                            features.insert(feature, value);
                         }
                     }
                 }
             }
         }
 
-        Ok(compacted.into_values().collect())
+        // metadata goes first, so that it is known when receiving the values
+        let mut events = self.metadata.events();
+        events.extend(compacted.into_values());
+
+        Ok(events)
     }
 }
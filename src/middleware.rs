@@ -116,6 +116,9 @@ pub struct Configuration {
     pub sources: HashMap<Address, Source>,
     #[serde(default)]
     pub sinks: HashMap<Address, Source>,
+    /// How metadata of features is sent to the cloud.
+    #[serde(default)]
+    pub metadata: data::MetadataMode,
 }
 
 #[derive(Clone, Debug, Eq, PartialEq, Deserialize)]
@@ -170,8 +173,8 @@ impl Update {
 impl Middleware {
     pub fn new(config: Configuration) -> Self {
         Self {
+            data: ActualDataLayer::new(config.metadata),
             config,
-            data: ActualDataLayer::new(),
         }
     }
 
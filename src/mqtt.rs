@@ -8,7 +8,7 @@ use rumqttc::{
     Transport,
 };
 use rustls::client::NoClientSessionStorage;
-use serde::Deserialize;
+use serde::{de::DeserializeOwned, Deserialize};
 use serde_json::Value;
 use std::{sync::Arc, time::Duration};
 use tokio::spawn;
@@ -173,6 +173,8 @@ impl MqttCloudConnector {
         let mut sink = Box::pin(sink);
 
         let mqtt = async {
+            // the time the connection was lost, if it was
+            let mut disconnected = None;
             loop {
                 match event_loop.poll().await {
                     Ok(rumqttc::Event::Incoming(Packet::ConnAck(ConnAck {
@@ -185,6 +187,9 @@ impl MqttCloudConnector {
                         {
                             log::warn!("Failed to subscribe to commands: {err}");
                         }
+                        if let Some(since) = disconnected.take() {
+                            Self::request_backfill(&mut sink, since).await;
+                        }
                     }
                     Ok(rumqttc::Event::Incoming(Packet::Publish(publish))) => {
                         log::debug!("Received command: {publish:?}");
@@ -195,6 +200,7 @@ impl MqttCloudConnector {
                     }
                     Err(err) => {
                         log::warn!("Connection error: {err}");
+                        disconnected.get_or_insert_with(chrono::Utc::now);
                     }
                 }
             }
@@ -256,14 +262,52 @@ impl MqttCloudConnector {
             pub node_id: String,
         }
 
-        match publish.topic.as_str() {
+        #[derive(Clone, Debug, Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct CallCommand {
+            pub connection: String,
+            pub object_id: String,
+            pub method_id: String,
+            #[serde(default)]
+            pub arguments: Vec<Value>,
+        }
+
+        #[derive(Clone, Debug, Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct ReadCommand {
+            pub connection: String,
+            pub node_ids: Vec<String>,
+            #[serde(default)]
+            pub attribute_id: Option<u32>,
+        }
+
+        #[derive(Clone, Debug, Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct HistoryCommand {
+            pub connection: String,
+            pub node_ids: Vec<String>,
+            pub start: String,
+            pub end: String,
+            #[serde(default)]
+            pub aggregate: Option<Value>,
+            #[serde(default)]
+            pub chunk_size: Option<u32>,
+        }
+
+        /// Information common to all commands.
+        #[derive(Clone, Debug, Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Correlation {
+            /// Echoed back unchanged, so any JSON value is accepted.
+            #[serde(default)]
+            pub correlation_id: Option<Value>,
+        }
+
+        let mut update = match publish.topic.as_str() {
             "command/inbox//write" => {
-                let command: WriteCommand = match serde_json::from_slice(publish.payload.as_ref()) {
-                    Ok(payload) => payload,
-                    Err(err) => {
-                        log::info!("Invalid command payload: {err}");
-                        return;
-                    }
+                let command: WriteCommand = match parse_command(&publish) {
+                    Some(command) => command,
+                    None => return,
                 };
 
                 let mut update = Update::new(
@@ -271,21 +315,137 @@ impl MqttCloudConnector {
                     command.connection.clone(),
                     command.value,
                 );
+                update
+                    .extensions
+                    .insert("command".to_string(), "write".into());
                 update
                     .extensions
                     .insert("nodeId".to_string(), command.node_id.into());
 
-                log::info!("Scheduling write command: {update:?}");
+                update
+            }
+            "command/inbox//call" => {
+                let command: CallCommand = match parse_command(&publish) {
+                    Some(command) => command,
+                    None => return,
+                };
+
+                let mut update = Update::new(
+                    ["cloud", "commands", &command.connection],
+                    command.connection.clone(),
+                    Value::Array(command.arguments),
+                );
+                update
+                    .extensions
+                    .insert("command".to_string(), "call".into());
+                update
+                    .extensions
+                    .insert("objectId".to_string(), command.object_id.into());
+                update
+                    .extensions
+                    .insert("methodId".to_string(), command.method_id.into());
+
+                update
+            }
+            "command/inbox//read" => {
+                let command: ReadCommand = match parse_command(&publish) {
+                    Some(command) => command,
+                    None => return,
+                };
+
+                let mut update = Update::new(
+                    ["cloud", "commands", &command.connection],
+                    command.connection.clone(),
+                    command.node_ids.into(),
+                );
+                update
+                    .extensions
+                    .insert("command".to_string(), "read".into());
+                if let Some(attribute_id) = command.attribute_id {
+                    update
+                        .extensions
+                        .insert("attributeId".to_string(), attribute_id.into());
+                }
 
-                let updates = vec![update];
+                update
+            }
+            "command/inbox//history" => {
+                let command: HistoryCommand = match parse_command(&publish) {
+                    Some(command) => command,
+                    None => return,
+                };
 
-                if let Err(err) = sink.send(middleware::Event { updates }).await {
-                    log::warn!("Failed to queue command: {err}");
+                let mut update = Update::new(
+                    ["cloud", "commands", &command.connection],
+                    command.connection.clone(),
+                    command.node_ids.into(),
+                );
+                update
+                    .extensions
+                    .insert("command".to_string(), "history".into());
+                update
+                    .extensions
+                    .insert("start".to_string(), command.start.into());
+                update
+                    .extensions
+                    .insert("end".to_string(), command.end.into());
+                if let Some(aggregate) = command.aggregate {
+                    update.extensions.insert("aggregate".to_string(), aggregate);
                 }
+                if let Some(chunk_size) = command.chunk_size {
+                    update
+                        .extensions
+                        .insert("chunkSize".to_string(), chunk_size.into());
+                }
+
+                update
             }
             _ => {
                 log::info!("Invalid command: {}", publish.topic);
+                return;
             }
+        };
+
+        if let Some(Correlation {
+            correlation_id: Some(correlation_id),
+        }) = parse_command(&publish)
+        {
+            update
+                .extensions
+                .insert("correlationId".to_string(), correlation_id);
+        }
+
+        log::info!("Scheduling command: {update:?}");
+
+        let updates = vec![update];
+
+        if let Err(err) = sink.send(middleware::Event { updates }).await {
+            log::warn!("Failed to queue command: {err}");
+        }
+    }
+
+    /// Request all connections to backfill the values, which might have been lost while being
+    /// disconnected.
+    async fn request_backfill<S, E>(sink: &mut S, since: chrono::DateTime<chrono::Utc>)
+    where
+        S: Sink<middleware::Event, Error = E> + Unpin,
+        E: std::error::Error,
+    {
+        log::info!("Reconnected, requesting backfill since: {since}");
+
+        // an empty channel addresses all connections
+        let mut update = Update::new(["cloud", "commands"], "", Value::Null);
+        update
+            .extensions
+            .insert("command".to_string(), "backfill".into());
+        update
+            .extensions
+            .insert("since".to_string(), since.to_rfc3339().into());
+
+        let updates = vec![update];
+
+        if let Err(err) = sink.send(middleware::Event { updates }).await {
+            log::warn!("Failed to queue backfill: {err}");
         }
     }
 
@@ -301,6 +461,19 @@ impl MqttCloudConnector {
     }
 }
 
+fn parse_command<T>(publish: &Publish) -> Option<T>
+where
+    T: DeserializeOwned,
+{
+    match serde_json::from_slice(publish.payload.as_ref()) {
+        Ok(payload) => Some(payload),
+        Err(err) => {
+            log::info!("Invalid command payload: {err}");
+            None
+        }
+    }
+}
+
 fn random_id() -> String {
     rand::thread_rng()
         .sample_iter(&Alphanumeric)
@@ -0,0 +1,224 @@
+//! Discovery of nodes by browsing the address space.
+
+use super::{opcua::client::prelude::*, resolve::NodeIds, services, Browse, Node};
+use std::collections::{HashSet, VecDeque};
+
+/// A node found while browsing.
+#[derive(Clone)]
+struct Found {
+    node_id: NodeId,
+    /// The browse names from the root, separated by `/`.
+    path: String,
+    depth: u32,
+}
+
+/// Browse the address space, returning all matching nodes.
+///
+/// The nodes get the browse path, relative to the root node, as alias.
+pub fn discover(
+    session: &Session,
+    node_ids: &NodeIds,
+    browse: &Browse,
+) -> anyhow::Result<Vec<Node>> {
+    let root = node_ids.resolve(session, &browse.root)?;
+    let node_classes = browse
+        .node_classes
+        .iter()
+        .map(|c| NodeClass::from(*c))
+        .collect::<Vec<_>>();
+    let data_types = browse
+        .data_types
+        .iter()
+        .map(|t| node_ids.resolve(session, t))
+        .collect::<Result<HashSet<_>, _>>()?;
+
+    let mut visited = HashSet::new();
+    visited.insert(root.clone());
+
+    let mut queue = VecDeque::new();
+    queue.push_back(Found {
+        node_id: root,
+        path: String::new(),
+        depth: 0,
+    });
+
+    let mut candidates = vec![];
+
+    while let Some(parent) = queue.pop_front() {
+        if parent.depth >= browse.depth {
+            continue;
+        }
+
+        for reference in browse_children(session, &parent.node_id)? {
+            let node_id = reference.node_id.node_id;
+            if !visited.insert(node_id.clone()) {
+                continue;
+            }
+
+            let name = reference.browse_name.name.as_ref();
+            let path = if parent.path.is_empty() {
+                name.to_string()
+            } else {
+                format!("{}/{}", parent.path, name)
+            };
+
+            let found = Found {
+                node_id,
+                path,
+                depth: parent.depth + 1,
+            };
+
+            if node_classes.contains(&reference.node_class) {
+                candidates.push(found.clone());
+            }
+
+            queue.push_back(found);
+        }
+    }
+
+    let candidates = if data_types.is_empty() {
+        candidates
+    } else {
+        let data_types = with_subtypes(session, data_types)?;
+        filter_data_types(session, candidates, &data_types)?
+    };
+
+    log::debug!(
+        "Discovered {} nodes below {}",
+        candidates.len(),
+        browse.root
+    );
+
+    Ok(candidates
+        .into_iter()
+        .map(|found| Node {
+            id: found.node_id.to_string(),
+            alias: Some(found.path),
+            monitoring: Default::default(),
+            triggered_nodes: vec![],
+        })
+        .collect())
+}
+
+/// Browse the hierarchical children (objects and variables) of a node.
+fn browse_children(
+    session: &Session,
+    node_id: &NodeId,
+) -> Result<Vec<ReferenceDescription>, StatusCode> {
+    browse_forward(
+        session,
+        node_id,
+        ReferenceTypeId::HierarchicalReferences,
+        NodeClass::Object as u32 | NodeClass::Variable as u32,
+    )
+}
+
+/// Browse the forward references of a node, of a reference type (or its subtypes).
+fn browse_forward(
+    session: &Session,
+    node_id: &NodeId,
+    reference_type: ReferenceTypeId,
+    node_class_mask: u32,
+) -> Result<Vec<ReferenceDescription>, StatusCode> {
+    let description = BrowseDescription {
+        node_id: node_id.clone(),
+        browse_direction: BrowseDirection::Forward,
+        reference_type_id: reference_type.into(),
+        include_subtypes: true,
+        node_class_mask,
+        result_mask: BrowseResultMask::All as u32,
+    };
+
+    let mut references = vec![];
+    let mut results = session.browse(&[description])?.unwrap_or_default();
+
+    while let Some(result) = results.pop() {
+        if !result.status_code.is_good() {
+            log::info!("Failed to browse {node_id}: {}", result.status_code);
+            return Err(result.status_code);
+        }
+
+        references.extend(result.references.into_iter().flatten());
+
+        // continue, if the server has more references for us
+        if !result.continuation_point.is_null() {
+            results = session
+                .browse_next(false, &[result.continuation_point])?
+                .unwrap_or_default();
+        }
+    }
+
+    Ok(references)
+}
+
+/// Add the subtypes of data types, e.g. `Duration` for `Double`.
+fn with_subtypes(
+    session: &Session,
+    data_types: HashSet<NodeId>,
+) -> Result<HashSet<NodeId>, StatusCode> {
+    let mut queue = data_types.iter().cloned().collect::<VecDeque<_>>();
+    let mut data_types = data_types;
+
+    while let Some(data_type) = queue.pop_front() {
+        let subtypes = browse_forward(
+            session,
+            &data_type,
+            ReferenceTypeId::HasSubtype,
+            NodeClass::DataType as u32,
+        )?;
+        for subtype in subtypes {
+            if data_types.insert(subtype.node_id.node_id.clone()) {
+                queue.push_back(subtype.node_id.node_id);
+            }
+        }
+    }
+
+    Ok(data_types)
+}
+
+/// Retain only the nodes, which have one of the data types.
+///
+/// The data types are read in batches, as servers limit the nodes per read.
+fn filter_data_types(
+    session: &Session,
+    candidates: Vec<Found>,
+    data_types: &HashSet<NodeId>,
+) -> Result<Vec<Found>, StatusCode> {
+    if candidates.is_empty() {
+        return Ok(candidates);
+    }
+
+    let limit = services::operation_limit(
+        session,
+        VariableId::Server_ServerCapabilities_OperationLimits_MaxNodesPerRead,
+    );
+
+    let mut retained = vec![];
+    for candidates in services::batches(candidates, limit) {
+        let nodes_to_read = candidates
+            .iter()
+            .map(|found| ReadValueId {
+                node_id: found.node_id.clone(),
+                attribute_id: AttributeId::DataType as u32,
+                index_range: UAString::null(),
+                data_encoding: QualifiedName::null(),
+            })
+            .collect::<Vec<_>>();
+
+        let values = session.read(&nodes_to_read, TimestampsToReturn::Neither, 0.0)?;
+
+        retained.extend(
+            candidates
+                .into_iter()
+                .zip(values)
+                .filter_map(|(found, value)| match value.value {
+                    Some(Variant::NodeId(data_type)) if data_types.contains(data_type.as_ref()) => {
+                        Some(found)
+                    }
+                    _ => None,
+                }),
+        );
+    }
+
+    Ok(retained)
+}
@@ -0,0 +1,303 @@
+//! Conversion of JSON values into variants, matching the data type of a node.
+
+use super::{opcua::client::prelude::*, structures::DataTypes, IntoVariant};
+use serde_json::Value;
+use std::str::FromStr;
+
+/// The type information of a variable.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TypeInfo {
+    /// The variant type, if the data type is a built-in type, or a sub-type of one.
+    pub variant_type: Option<VariantTypeId>,
+    pub value_rank: i32,
+    /// The data type, for types which are not built-in.
+    pub data_type: Option<NodeId>,
+}
+
+/// Read the `DataType` and `ValueRank` attributes of a variable.
+pub fn read_type_info(
+    session: &Session,
+    data_types: &DataTypes,
+    node_id: &NodeId,
+) -> Result<TypeInfo, StatusCode> {
+    let read = |attribute_id: AttributeId| ReadValueId {
+        node_id: node_id.clone(),
+        attribute_id: attribute_id as u32,
+        index_range: UAString::null(),
+        data_encoding: QualifiedName::null(),
+    };
+
+    let mut values = session
+        .read(
+            &[read(AttributeId::DataType), read(AttributeId::ValueRank)],
+            TimestampsToReturn::Neither,
+            0.0,
+        )?
+        .into_iter();
+
+    let mut next = || -> Result<Option<Variant>, StatusCode> {
+        match values.next() {
+            Some(DataValue {
+                status: Some(status),
+                ..
+            }) if !status.is_good() => Err(status),
+            Some(value) => Ok(value.value),
+            None => Err(StatusCode::BadUnexpectedError),
+        }
+    };
+
+    let (variant_type, data_type) = match next()? {
+        Some(Variant::NodeId(data_type)) => match data_types.variant_type(session, &data_type) {
+            Some(variant_type) => (Some(variant_type), None),
+            None => (None, Some(*data_type)),
+        },
+        _ => (None, None),
+    };
+    let value_rank = match next()? {
+        Some(Variant::Int32(value_rank)) => value_rank,
+        _ => -1,
+    };
+
+    Ok(TypeInfo {
+        variant_type,
+        value_rank,
+        data_type,
+    })
+}
+
+/// Convert a JSON value into a variant, matching the type information.
+///
+/// Objects are still converted using the tagged form of [`IntoVariant`], as are values of
+/// non built-in types.
+pub fn coerce(value: Value, type_info: &TypeInfo) -> Result<Variant, StatusCode> {
+    let variant_type = match type_info.variant_type {
+        Some(variant_type) if !value.is_object() => variant_type,
+        _ => return Ok(value.into_variant()),
+    };
+
+    match value {
+        Value::Array(values) if type_info.value_rank != -1 => {
+            let mut dimensions = vec![];
+            let mut flattened = vec![];
+            flatten(Value::Array(values), 0, &mut dimensions, &mut flattened)?;
+
+            let values = flattened
+                .into_iter()
+                .map(|value| coerce_scalar(value, variant_type))
+                .collect::<Result<Vec<_>, _>>()?;
+
+            let array = if dimensions.len() > 1 {
+                Array::new_multi(variant_type, values, dimensions)
+            } else {
+                Array::new_single(variant_type, values)
+            };
+
+            array
+                .map(|array| Variant::Array(Box::new(array)))
+                .map_err(|_| StatusCode::BadTypeMismatch)
+        }
+        Value::Array(_) => Err(StatusCode::BadTypeMismatch),
+        value => coerce_scalar(value, variant_type),
+    }
+}
+
+/// Flatten nested arrays, recording the dimensions.
+///
+/// All arrays of the same level must have the same length.
+fn flatten(
+    value: Value,
+    level: usize,
+    dimensions: &mut Vec<u32>,
+    values: &mut Vec<Value>,
+) -> Result<(), StatusCode> {
+    match value {
+        Value::Array(items) => {
+            let len = items.len() as u32;
+            match dimensions.get(level) {
+                None => dimensions.push(len),
+                Some(expected) if *expected != len => return Err(StatusCode::BadIndexRangeInvalid),
+                Some(_) => {}
+            }
+            for item in items {
+                flatten(item, level + 1, dimensions, values)?;
+            }
+            Ok(())
+        }
+        value if level == dimensions.len() => {
+            values.push(value);
+            Ok(())
+        }
+        _ => Err(StatusCode::BadIndexRangeInvalid),
+    }
+}
+
+fn coerce_scalar(value: Value, variant_type: VariantTypeId) -> Result<Variant, StatusCode> {
+    fn int<T: TryFrom<i64>>(value: &Value) -> Result<T, StatusCode> {
+        let value = value.as_i64().ok_or(StatusCode::BadTypeMismatch)?;
+        T::try_from(value).map_err(|_| StatusCode::BadOutOfRange)
+    }
+
+    fn uint<T: TryFrom<u64>>(value: &Value) -> Result<T, StatusCode> {
+        let value = value.as_u64().ok_or(StatusCode::BadTypeMismatch)?;
+        T::try_from(value).map_err(|_| StatusCode::BadOutOfRange)
+    }
+
+    fn float(value: &Value) -> Result<f64, StatusCode> {
+        value.as_f64().ok_or(StatusCode::BadTypeMismatch)
+    }
+
+    fn string(value: &Value) -> Result<&str, StatusCode> {
+        value.as_str().ok_or(StatusCode::BadTypeMismatch)
+    }
+
+    match value {
+        Value::Null => return Ok(Variant::Empty),
+        Value::Object(_) => return Ok(value.into_variant()),
+        _ => {}
+    }
+
+    let value = match variant_type {
+        VariantTypeId::Boolean => {
+            Variant::Boolean(value.as_bool().ok_or(StatusCode::BadTypeMismatch)?)
+        }
+        VariantTypeId::SByte => Variant::SByte(int(&value)?),
+        VariantTypeId::Byte => Variant::Byte(uint(&value)?),
+        VariantTypeId::Int16 => Variant::Int16(int(&value)?),
+        VariantTypeId::UInt16 => Variant::UInt16(uint(&value)?),
+        VariantTypeId::Int32 => Variant::Int32(int(&value)?),
+        VariantTypeId::UInt32 => Variant::UInt32(uint(&value)?),
+        VariantTypeId::Int64 => Variant::Int64(int(&value)?),
+        VariantTypeId::UInt64 => Variant::UInt64(uint(&value)?),
+        VariantTypeId::Float => Variant::Float(float(&value)? as f32),
+        VariantTypeId::Double => Variant::Double(float(&value)?),
+        VariantTypeId::String => Variant::from(string(&value)?),
+        VariantTypeId::DateTime => {
+            let value = chrono::DateTime::parse_from_rfc3339(string(&value)?)
+                .map_err(|_| StatusCode::BadTypeMismatch)?;
+            Variant::from(DateTime::from(value.with_timezone(&chrono::Utc)))
+        }
+        VariantTypeId::Guid => {
+            let value = Guid::from_str(string(&value)?).map_err(|_| StatusCode::BadTypeMismatch)?;
+            Variant::from(value)
+        }
+        VariantTypeId::ByteString => {
+            let value = base64::decode(string(&value)?).map_err(|_| StatusCode::BadTypeMismatch)?;
+            Variant::from(ByteString::from(value))
+        }
+        VariantTypeId::NodeId => {
+            let value =
+                NodeId::from_str(string(&value)?).map_err(|_| StatusCode::BadTypeMismatch)?;
+            Variant::from(value)
+        }
+        VariantTypeId::LocalizedText => Variant::from(LocalizedText::new("", string(&value)?)),
+        _ => value.into_variant(),
+    };
+
+    Ok(value)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn scalar(variant_type: VariantTypeId) -> TypeInfo {
+        TypeInfo {
+            variant_type: Some(variant_type),
+            value_rank: -1,
+            data_type: None,
+        }
+    }
+
+    fn array(variant_type: VariantTypeId, value_rank: i32) -> TypeInfo {
+        TypeInfo {
+            variant_type: Some(variant_type),
+            value_rank,
+            data_type: None,
+        }
+    }
+
+    #[test]
+    fn test_coerce_scalar() {
+        assert_eq!(
+            coerce(json!(100), &scalar(VariantTypeId::Int16)),
+            Ok(Variant::Int16(100))
+        );
+        assert_eq!(
+            coerce(json!(1.5), &scalar(VariantTypeId::Float)),
+            Ok(Variant::Float(1.5))
+        );
+        assert_eq!(
+            coerce(json!(100000), &scalar(VariantTypeId::Int16)),
+            Err(StatusCode::BadOutOfRange)
+        );
+        assert_eq!(
+            coerce(json!(-1), &scalar(VariantTypeId::UInt32)),
+            Err(StatusCode::BadTypeMismatch)
+        );
+        assert_eq!(
+            coerce(json!("foo"), &scalar(VariantTypeId::Boolean)),
+            Err(StatusCode::BadTypeMismatch)
+        );
+    }
+
+    #[test]
+    fn test_coerce_date_time() {
+        let expected = chrono::DateTime::parse_from_rfc3339("2022-01-02T03:04:05Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        assert_eq!(
+            coerce(
+                json!("2022-01-02T03:04:05Z"),
+                &scalar(VariantTypeId::DateTime)
+            ),
+            Ok(Variant::DateTime(Box::new(DateTime::from(expected))))
+        );
+    }
+
+    #[test]
+    fn test_coerce_tagged() {
+        assert_eq!(
+            coerce(json!({"Int32": 100}), &scalar(VariantTypeId::Int16)),
+            Ok(Variant::Int32(100))
+        );
+    }
+
+    #[test]
+    fn test_coerce_array() {
+        assert_eq!(
+            coerce(json!([1, 2, 3]), &array(VariantTypeId::Byte, 1)),
+            Ok(Variant::Array(Box::new(
+                Array::new_single(
+                    VariantTypeId::Byte,
+                    [Variant::Byte(1), Variant::Byte(2), Variant::Byte(3)]
+                )
+                .unwrap()
+            )))
+        );
+        assert_eq!(
+            coerce(json!([[1, 2], [3, 4]]), &array(VariantTypeId::Int16, 2)),
+            Ok(Variant::Array(Box::new(
+                Array::new_multi(
+                    VariantTypeId::Int16,
+                    [
+                        Variant::Int16(1),
+                        Variant::Int16(2),
+                        Variant::Int16(3),
+                        Variant::Int16(4)
+                    ],
+                    vec![2, 2]
+                )
+                .unwrap()
+            )))
+        );
+        assert_eq!(
+            coerce(json!([[1, 2], [3]]), &array(VariantTypeId::Int16, 2)),
+            Err(StatusCode::BadIndexRangeInvalid)
+        );
+        assert_eq!(
+            coerce(json!([1, 2]), &scalar(VariantTypeId::Int16)),
+            Err(StatusCode::BadTypeMismatch)
+        );
+    }
+}
@@ -0,0 +1,615 @@
+//! Handling of commands, received from the cloud.
+
+use super::{
+    coerce::{self, TypeInfo},
+    history::{self, Aggregate, Backfill, Details},
+    method_address, now,
+    opcua::client::prelude::*,
+    resolve::NodeIds,
+    structures::DataTypes,
+    EventSender, IntoVariant,
+};
+use crate::{
+    middleware::{Address, Update},
+    ToJson,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+/// The default number of values per node, in a chunk of raw history.
+const DEFAULT_HISTORY_CHUNK_SIZE: u32 = 1_000;
+
+/// Executes commands on a session, sending back the results.
+#[derive(Clone)]
+pub struct CommandHandler {
+    connection: String,
+    tx: EventSender,
+    /// Type information of written nodes, by node ID
+    types: Arc<Mutex<HashMap<NodeId, TypeInfo>>>,
+    backfill: Backfill,
+    node_ids: NodeIds,
+    data_types: DataTypes,
+}
+
+impl CommandHandler {
+    pub fn new(
+        connection: String,
+        tx: EventSender,
+        backfill: Backfill,
+        node_ids: NodeIds,
+        data_types: DataTypes,
+    ) -> Self {
+        Self {
+            connection,
+            tx,
+            types: Default::default(),
+            backfill,
+            node_ids,
+            data_types,
+        }
+    }
+
+    pub fn handle(&mut self, session: &Session, update: Update) {
+        match command(&update).as_str() {
+            "write" => self.write(session, update),
+            "call" => self.call(session, update),
+            "read" => self.read(session, update),
+            "backfill" => self.backfill(session, update),
+            "history" => self.history(session, update),
+            command => log::info!("Unknown command: {command}"),
+        }
+    }
+
+    /// Reject a command without executing it, e.g. when not being connected.
+    ///
+    /// The response is sent to the same address as the one of the executed command.
+    pub fn reject(&mut self, update: Update, status: StatusCode) {
+        let command = command(&update);
+        let mut value = json!({
+            "timestamp": now(),
+            "status": status.name(),
+        });
+
+        let address = |command: &str| command_address(&self.connection, command);
+
+        let address = match command.as_str() {
+            "write" => {
+                let node_id = update
+                    .extensions
+                    .get("nodeId")
+                    .and_then(|id| id.as_str())
+                    .or_else(|| update.address.last().map(|s| s.as_str()));
+                value["nodeId"] = node_id.into();
+                address("write")
+            }
+            "read" => address("read"),
+            "history" => {
+                // also marks the end of the history
+                value["chunks"] = 0.into();
+                value["complete"] = true.into();
+                address("history")
+            }
+            "call" => {
+                // without a session, only plain node IDs can be used
+                let method_id = update
+                    .extensions
+                    .get("methodId")
+                    .and_then(|id| id.as_str())
+                    .and_then(|id| NodeId::from_str(id).ok());
+                match method_id {
+                    Some(method_id) => method_address(&self.connection, &method_id),
+                    None => address("call"),
+                }
+            }
+            command => {
+                log::info!("Dropping {command} command: {status}");
+                return;
+            }
+        };
+
+        let response = self.response(address, &update.extensions, value);
+        self.tx.update_sync([response]);
+    }
+
+    fn call(&mut self, session: &Session, update: Update) {
+        log::debug!("Calling method: {update:?}");
+
+        let node_id = |name: &str| -> Result<NodeId, StatusCode> {
+            let node_id = update
+                .extensions
+                .get(name)
+                .and_then(|id| id.as_str())
+                .ok_or(StatusCode::BadNodeIdInvalid)?;
+            self.node_ids.resolve(session, node_id).map_err(|status| {
+                log::info!("Failed to parse {name}: {status}");
+                status
+            })
+        };
+
+        let (object_id, method_id) = match (node_id("objectId"), node_id("methodId")) {
+            (Ok(object_id), Ok(method_id)) => (object_id, method_id),
+            (object_id, method_id) => {
+                // respond on the address of the method, if known
+                let address = match &method_id {
+                    Ok(method_id) => method_address(&self.connection, method_id),
+                    Err(_) => command_address(&self.connection, "call"),
+                };
+                let status = method_id
+                    .and(object_id)
+                    .err()
+                    .unwrap_or(StatusCode::BadNodeIdInvalid);
+                let response = self.response(
+                    address,
+                    &update.extensions,
+                    json!({
+                        "timestamp": now(),
+                        "status": status.name(),
+                    }),
+                );
+                self.tx.update_sync([response]);
+                return;
+            }
+        };
+
+        let input_arguments = match update.value {
+            Value::Null => vec![],
+            Value::Array(values) => values.into_iter().map(|v| v.into_variant()).collect(),
+            value => vec![value.into_variant()],
+        };
+
+        let address = method_address(&self.connection, &method_id);
+
+        // results may be structures, which aren't used by any subscribed node
+        self.data_types.load_output_arguments(session, &method_id);
+
+        let result = session.call(CallMethodRequest {
+            object_id,
+            method_id,
+            input_arguments: Some(input_arguments),
+        });
+
+        let value = match result {
+            Ok(result) => json!({
+                "timestamp": now(),
+                "status": result.status_code.name(),
+                "inputArguments": result
+                    .input_argument_results
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|status| status.to_json())
+                    .collect::<Vec<_>>(),
+                "outputArguments": result
+                    .output_arguments
+                    .unwrap_or_default()
+                    .into_iter()
+                    .map(|value| self.data_types.variant_to_json(value))
+                    .collect::<Vec<_>>(),
+            }),
+            Err(status) => {
+                log::info!("Failed to call method: {status}");
+                json!({
+                    "timestamp": now(),
+                    "status": status.name(),
+                })
+            }
+        };
+
+        let response = self.response(address, &update.extensions, value);
+        self.tx.update_sync([response]);
+    }
+
+    fn read(&mut self, session: &Session, update: Update) {
+        log::debug!("Reading: {update:?}");
+
+        let node_ids = match &update.value {
+            Value::Array(node_ids) => node_ids
+                .iter()
+                .filter_map(|node_id| node_id.as_str())
+                .map(|node_id| node_id.to_string())
+                .collect::<Vec<_>>(),
+            _ => vec![],
+        };
+
+        // an unknown attribute is rejected by the server, but it must fit into the request
+        let attribute_id = match update.extensions.get("attributeId") {
+            None => Ok(AttributeId::Value as u32),
+            Some(id) => id
+                .as_u64()
+                .and_then(|id| u32::try_from(id).ok())
+                .ok_or(StatusCode::BadAttributeIdInvalid),
+        };
+
+        let request = attribute_id.and_then(|attribute_id| {
+            let nodes_to_read = node_ids
+                .iter()
+                .map(|node_id| {
+                    self.node_ids
+                        .resolve(session, node_id)
+                        .map(|node_id| ReadValueId {
+                            node_id,
+                            attribute_id,
+                            index_range: UAString::null(),
+                            data_encoding: QualifiedName::null(),
+                        })
+                })
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok((attribute_id, nodes_to_read))
+        });
+
+        let result = match request {
+            Ok((attribute_id, nodes_to_read)) => {
+                // values may be structures, which aren't used by any subscribed node
+                if attribute_id == AttributeId::Value as u32 {
+                    let node_ids = nodes_to_read
+                        .iter()
+                        .map(|node| node.node_id.clone())
+                        .collect::<Vec<_>>();
+                    self.data_types.load_variables(session, &node_ids);
+                }
+                session
+                    .read(&nodes_to_read, TimestampsToReturn::Both, 0.0)
+                    .map(|values| (attribute_id, values))
+            }
+            Err(status) => {
+                log::info!("Failed to parse the read request: {status}");
+                Err(status)
+            }
+        };
+
+        let value = match result {
+            Ok((attribute_id, values)) => {
+                // in the order of the request, which may have duplicates
+                let values = node_ids
+                    .into_iter()
+                    .zip(values)
+                    .map(|(node_id, value)| {
+                        json!({
+                            "nodeId": node_id,
+                            "attributeId": attribute_id,
+                            "value": self.data_types.to_json(value),
+                        })
+                    })
+                    .collect::<Vec<_>>();
+                json!({
+                    "timestamp": now(),
+                    "status": StatusCode::Good.name(),
+                    "values": values,
+                })
+            }
+            Err(status) => {
+                log::info!("Failed to read: {status}");
+                json!({
+                    "timestamp": now(),
+                    "status": status.name(),
+                })
+            }
+        };
+
+        let response = self.response(
+            ["opcua", self.connection.as_str(), "commands", "read"],
+            &update.extensions,
+            value,
+        );
+        self.tx.update_sync([response]);
+    }
+
+    /// Read the history of nodes, and send it back in chunks.
+    fn history(&mut self, session: &Session, update: Update) {
+        log::debug!("Reading history: {update:?}");
+
+        let respond = |this: &mut Self, value: Value| {
+            let response = this.response(
+                ["opcua", this.connection.as_str(), "commands", "history"],
+                &update.extensions,
+                value,
+            );
+            // send one by one, in order, so that the chunks don't get compacted
+            this.tx.update_blocking([response]);
+        };
+
+        let node_ids = match &update.value {
+            Value::Array(node_ids) => node_ids
+                .iter()
+                .filter_map(|node_id| node_id.as_str())
+                .map(|node_id| node_id.to_string())
+                .collect::<Vec<_>>(),
+            _ => vec![],
+        };
+
+        let time = |name: &str| {
+            let value = update.extensions.get(name).and_then(|time| time.as_str())?;
+            match chrono::DateTime::parse_from_rfc3339(value) {
+                Ok(time) => Some(time.with_timezone(&chrono::Utc)),
+                Err(err) => {
+                    log::info!("Invalid {name} time: {err}");
+                    None
+                }
+            }
+        };
+
+        let chunk_size = update
+            .extensions
+            .get("chunkSize")
+            .and_then(|size| size.as_u64())
+            .map(|size| size as u32)
+            .unwrap_or(DEFAULT_HISTORY_CHUNK_SIZE);
+
+        // pages of history, read one after the other
+        let pages = match (time("start"), time("end")) {
+            (Some(start), Some(end)) => match update.extensions.get("aggregate") {
+                None | Some(Value::Null) => Ok(vec![Details::raw_paged(start, end, chunk_size)]),
+                Some(aggregate) => match serde_json::from_value::<Aggregate>(aggregate.clone()) {
+                    Ok(aggregate) => Ok(Details::processed_paged(
+                        start,
+                        end,
+                        &aggregate,
+                        node_ids.len(),
+                        chunk_size,
+                    )),
+                    Err(err) => {
+                        log::info!("Invalid aggregate: {err}");
+                        Err(StatusCode::BadAggregateInvalidInputs)
+                    }
+                },
+            },
+            _ => Err(StatusCode::BadInvalidArgument),
+        };
+
+        let parsed_node_ids = node_ids
+            .iter()
+            .map(|node_id| self.node_ids.resolve(session, node_id))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|status| {
+                log::info!("Failed to parse NodeId: {status}");
+                status
+            });
+
+        let data_types = self.data_types.clone();
+        let mut chunks = 0;
+        let mut statuses = vec![StatusCode::Good; node_ids.len()];
+        let result = pages.and_then(|pages| {
+            let parsed_node_ids = parsed_node_ids?;
+            for details in pages {
+                let read = history::read(session, &details, &parsed_node_ids, |index, values| {
+                    chunks += 1;
+                    respond(
+                        self,
+                        json!({
+                            "timestamp": now(),
+                            "nodeId": node_ids[index],
+                            "chunk": chunks,
+                            "values": values
+                                .into_iter()
+                                .map(|value| data_types.to_json(value))
+                                .collect::<Vec<_>>(),
+                        }),
+                    );
+                })?;
+
+                // keep the first failure of a node, pages after it are empty
+                for (status, read) in statuses.iter_mut().zip(read) {
+                    if status.is_good() {
+                        *status = read;
+                    }
+                }
+            }
+            Ok(())
+        });
+
+        // the request fails as a whole, or with the first failed node
+        let status = match result {
+            Ok(()) => statuses
+                .iter()
+                .copied()
+                .find(|status| !status.is_good())
+                .unwrap_or(StatusCode::Good),
+            Err(status) => {
+                log::info!("Failed to read history: {status}");
+                status
+            }
+        };
+
+        // mark the end of the history
+        respond(
+            self,
+            json!({
+                "timestamp": now(),
+                "status": status.name(),
+                "chunks": chunks,
+                "complete": true,
+                "nodes": node_ids
+                    .iter()
+                    .zip(&statuses)
+                    .map(|(node_id, status)| json!({
+                        "nodeId": node_id,
+                        "status": status.name(),
+                    }))
+                    .collect::<Vec<_>>(),
+            }),
+        );
+    }
+
+    /// Backfill the subscriptions, e.g. after the cloud connection was lost.
+    fn backfill(&mut self, session: &Session, update: Update) {
+        log::debug!("Backfilling: {update:?}");
+
+        let since = update
+            .extensions
+            .get("since")
+            .and_then(|since| since.as_str())
+            .and_then(|since| match chrono::DateTime::parse_from_rfc3339(since) {
+                Ok(since) => Some(since.with_timezone(&chrono::Utc)),
+                Err(err) => {
+                    log::info!("Invalid backfill timestamp: {err}");
+                    None
+                }
+            });
+
+        self.backfill.run(
+            &self.connection,
+            session,
+            since,
+            &self.data_types,
+            &mut self.tx,
+        );
+    }
+
+    fn write(&mut self, session: &Session, update: Update) {
+        log::debug!("Writing command: {update:?}");
+
+        let node_id = update
+            .extensions
+            .get("nodeId")
+            .and_then(|id| id.as_str())
+            .or_else(|| update.address.last().map(|s| s.as_str()));
+
+        let respond = |this: &mut Self, status: StatusCode| {
+            let response = this.response(
+                ["opcua", this.connection.as_str(), "commands", "write"],
+                &update.extensions,
+                json!({
+                    "timestamp": now(),
+                    "nodeId": node_id,
+                    "status": status.name(),
+                }),
+            );
+            this.tx.update_sync([response]);
+        };
+
+        let node_id = match node_id {
+            Some(node_id) => node_id,
+            None => {
+                log::info!("Write command without a node ID");
+                respond(self, StatusCode::BadNodeIdInvalid);
+                return;
+            }
+        };
+
+        let parsed_node_id = match self.node_ids.resolve(session, node_id) {
+            Ok(node_id) => node_id,
+            Err(status) => {
+                log::info!("Failed to parse NodeId: {status}");
+                respond(self, status);
+                return;
+            }
+        };
+
+        let value = match self.type_info(session, &parsed_node_id) {
+            Some(type_info) => match self.data_types.encode(session, &type_info, &update.value) {
+                Some(value) => value,
+                None => coerce::coerce(update.value, &type_info),
+            },
+            None => Ok(update.value.into_variant()),
+        };
+
+        let value = match value {
+            Ok(value) => DataValue::value_only(value),
+            Err(status) => {
+                log::info!("Unable to convert value for {node_id}: {status}");
+                respond(self, status);
+                return;
+            }
+        };
+
+        let value = WriteValue {
+            node_id: parsed_node_id.clone(),
+            attribute_id: AttributeId::Value as u32,
+            index_range: Default::default(),
+            value,
+        };
+
+        let status = match session.write(&[value]) {
+            Ok(result) => result
+                .into_iter()
+                .next()
+                .unwrap_or(StatusCode::BadUnexpectedError),
+            Err(err) => {
+                log::info!("Failed to write: {err}");
+                err
+            }
+        };
+
+        if !status.is_good() {
+            log::info!("Write to {node_id} rejected: {status}");
+        }
+
+        if status == StatusCode::BadTypeMismatch {
+            // the type of the node might have changed, so read it again next time
+            self.types.lock().unwrap().remove(&parsed_node_id);
+        }
+
+        respond(self, status);
+    }
+
+    /// Get the type information of a node, from the cache or by reading it.
+    fn type_info(&self, session: &Session, node_id: &NodeId) -> Option<TypeInfo> {
+        if let Some(type_info) = self.types.lock().unwrap().get(node_id) {
+            return Some(type_info.clone());
+        }
+
+        match coerce::read_type_info(session, &self.data_types, node_id) {
+            Ok(type_info) => {
+                log::debug!("Type of {node_id}: {type_info:?}");
+                self.types
+                    .lock()
+                    .unwrap()
+                    .insert(node_id.clone(), type_info.clone());
+                Some(type_info)
+            }
+            Err(status) => {
+                log::info!("Failed to read type of {node_id}: {status}");
+                None
+            }
+        }
+    }
+
+    /// Create the response to a command, carrying the correlation ID of the command (if present).
+    fn response<I, S>(
+        &self,
+        address: I,
+        command: &HashMap<String, Value>,
+        mut value: Value,
+    ) -> Update
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        if let (Some(correlation_id), Value::Object(value)) =
+            (command.get("correlationId"), &mut value)
+        {
+            value.insert("correlationId".to_string(), correlation_id.clone());
+        }
+
+        let mut update = Update::new(address, &self.connection, value);
+        // responses are not part of the state of the connection
+        update
+            .extensions
+            .insert("transient".to_string(), true.into());
+        update
+    }
+}
+
+/// Get the name of a command, defaulting to `write`.
+/// The address of the responses to a command, which isn't specific to a node.
+fn command_address(connection: &str, command: &str) -> Address {
+    vec![
+        "opcua".to_string(),
+        connection.to_string(),
+        "commands".to_string(),
+        command.to_string(),
+    ]
+    .into()
+}
+
+fn command(update: &Update) -> String {
+    update
+        .extensions
+        .get("command")
+        .and_then(|command| command.as_str())
+        .unwrap_or("write")
+        .to_string()
+}
@@ -1,7 +1,11 @@
-use crate::opcua::opcua::types::TimestampsToReturn;
-use serde::Deserialize;
-use std::collections::HashMap;
+use crate::opcua::opcua::types::{
+    DataChangeTrigger, MessageSecurityMode, NodeClass, NodeId, TimestampsToReturn,
+};
+use serde::{de, Deserialize, Deserializer};
+use std::collections::{HashMap, HashSet};
 use std::default::Default;
+use std::path::PathBuf;
+use std::str::FromStr;
 use std::time::Duration;
 
 #[derive(Clone, Debug, Deserialize)]
@@ -15,8 +19,23 @@ pub struct Configuration {
 pub struct Connection {
     pub url: String,
 
-    pub security_policy: String,
-    pub security_mode: String,
+    /// URLs of redundant servers, failed over to when the current server fails.
+    #[serde(default)]
+    pub redundant_urls: Vec<String>,
+
+    /// Read the `ServiceLevel` of the servers, failing over to the healthiest one.
+    #[serde(default)]
+    pub check_service_level: bool,
+
+    /// The security policy, selected from the server's endpoints if missing.
+    #[serde(default)]
+    pub security_policy: Option<SecurityPolicy>,
+    /// The security mode, selected from the server's endpoints if missing.
+    #[serde(default)]
+    pub security_mode: Option<SecurityMode>,
+    /// The minimum security policy, when selecting an endpoint.
+    #[serde(default)]
+    pub min_security_policy: SecurityPolicy,
 
     #[serde(default)]
     pub auto_accept_server_certificate: bool,
@@ -24,17 +43,65 @@ pub struct Connection {
     #[serde(default)]
     pub create_sample_keypair: bool,
 
+    /// The application URI, must match the client certificate.
+    #[serde(default)]
+    pub application_uri: Option<String>,
+
+    /// The PKI directory, defaults to the `PKI_DIR` environment variable.
+    ///
+    /// Connections sharing a PKI directory also share the trusted server certificates, set a
+    /// directory per connection to keep those apart.
+    #[serde(default)]
+    pub pki_dir: Option<PathBuf>,
+
+    /// The client certificate (DER), relative to the PKI directory.
+    #[serde(default)]
+    pub client_certificate: Option<PathBuf>,
+
+    /// The private key of the client certificate (PEM), relative to the PKI directory.
+    #[serde(default)]
+    pub client_private_key: Option<PathBuf>,
+
+    /// Server certificates (DER) to trust.
+    #[serde(default)]
+    pub trusted_server_certificates: Vec<PathBuf>,
+
+    /// Trust the server certificate with this SHA-1 thumbprint (hex encoded).
+    #[serde(default)]
+    pub server_certificate_thumbprint: Option<String>,
+
     #[serde(default)]
     #[serde(with = "humantime_serde")]
     pub session_timeout: Option<Duration>,
 
+    /// The number of attempts to reconnect a session, before giving up on the server.
+    ///
+    /// Defaults to retrying forever, or a few times when failing over to redundant servers.
     #[serde(default)]
     pub session_retry_limit: Option<u16>,
 
+    /// Restarting the connection, when it failed.
     #[serde(default)]
-    pub credentials: Credentials,
+    pub reconnect: Reconnect,
+
+    /// Monitor the status of the server.
+    #[serde(default)]
+    pub monitor_server: Option<ServerMonitoring>,
+
+    /// Load the structures of the server, to decode and encode structured values.
+    ///
+    /// Disabled by default, as loading the data types requires additional requests.
+    #[serde(default)]
+    pub decode_structures: bool,
+
+    /// The JSON encoding of values.
+    #[serde(default)]
+    pub json_encoding: JsonEncoding,
 
     #[serde(default)]
+    pub credentials: Credentials,
+
+    #[serde(default, deserialize_with = "deserialize_subscriptions")]
     pub subscriptions: HashMap<String, Subscription>,
 }
 
@@ -43,12 +110,371 @@ pub struct Connection {
 pub struct Subscription {
     #[serde(default = "defaults::publish_interval", with = "humantime_serde")]
     pub publish_interval: Duration,
-    pub nodes: Vec<String>,
+    /// The nodes to monitor, node IDs and aliases must be unique.
+    #[serde(default, deserialize_with = "deserialize_nodes")]
+    pub nodes: Vec<Node>,
+    #[serde(flatten)]
+    pub monitoring: MonitoringSettings,
+    /// Subscribe to events, in addition to the nodes.
+    #[serde(default)]
+    pub events: Option<Events>,
+    /// Discover additional nodes by browsing the server.
+    #[serde(default)]
+    pub browse: Option<Browse>,
+    /// The requested lifetime count, in publish intervals without a publish request.
+    ///
+    /// Must be at least three times the maximum keep-alive count.
+    #[serde(default = "defaults::lifetime_count")]
+    pub lifetime_count: u32,
+    /// The requested maximum keep-alive count, in publish intervals without a notification.
+    #[serde(default = "defaults::max_keep_alive_count")]
+    pub max_keep_alive_count: u32,
+    /// The maximum number of notifications per publish response, zero for no limit.
+    #[serde(default)]
+    pub max_notifications_per_publish: u32,
+    /// The priority, relative to other subscriptions of the session.
+    #[serde(default)]
+    pub priority: u8,
+    #[serde(default = "defaults::publishing_enabled")]
+    pub publishing_enabled: bool,
+    /// Backfill values missed during a connection loss, from the history of the server.
+    #[serde(default)]
+    pub backfill: bool,
+    /// How values are acquired from the server.
+    #[serde(default)]
+    pub mode: SubscriptionMode,
+    /// Don't report values which didn't change since the last poll.
+    #[serde(default)]
+    pub suppress_unchanged: bool,
+    /// Periodically retry creating monitored items, which failed to be created.
+    #[serde(default, with = "humantime_serde")]
+    pub retry_interval: Option<Duration>,
+    /// Read the metadata of the nodes once, and attach it to their updates.
     #[serde(default)]
-    pub timestamps: Timestamps,
+    pub metadata: bool,
 }
 
-#[derive(Clone, Copy, Debug, Deserialize)]
+impl Subscription {
+    /// Validate settings, which depend on each other.
+    pub fn validate(&self) -> Result<(), String> {
+        if self.lifetime_count < self.max_keep_alive_count.saturating_mul(3) {
+            return Err(format!(
+                "lifetime count ({}) must be at least three times the max keep-alive count ({})",
+                self.lifetime_count, self.max_keep_alive_count
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Deserialize the subscriptions of a connection, validating each of them.
+fn deserialize_subscriptions<'de, D>(
+    deserializer: D,
+) -> Result<HashMap<String, Subscription>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let subscriptions = HashMap::<String, Subscription>::deserialize(deserializer)?;
+    for (id, subscription) in &subscriptions {
+        subscription
+            .validate()
+            .map_err(|err| de::Error::custom(format!("invalid subscription {id}: {err}")))?;
+    }
+    Ok(subscriptions)
+}
+
+/// The JSON encoding of values.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum JsonEncoding {
+    /// A simplified encoding of plain values, losing type information like the variant type.
+    #[serde(alias = "simple")]
+    Simple,
+    /// The reversible OPC UA JSON encoding, as defined by part 6 of the specification.
+    ///
+    /// Structures keep their binary body, as decoded fields would lose their types. Part 6
+    /// allows this for structures as well.
+    #[serde(alias = "reversible")]
+    Reversible,
+    /// The non-reversible OPC UA JSON encoding, as defined by part 6 of the specification.
+    ///
+    /// Variants are encoded as plain values, and namespaces by their URI.
+    #[serde(alias = "nonReversible")]
+    NonReversible,
+}
+
+impl Default for JsonEncoding {
+    fn default() -> Self {
+        JsonEncoding::Simple
+    }
+}
+
+/// The mode of a subscription.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum SubscriptionMode {
+    /// Use an OPC UA subscription.
+    #[serde(alias = "subscribe")]
+    Subscribe,
+    /// Cyclically read the values, for servers lacking (proper) subscription support.
+    ///
+    /// Only the `timestamps` setting of the subscription applies, events are not supported.
+    #[serde(alias = "poll")]
+    Poll,
+}
+
+impl Default for SubscriptionMode {
+    fn default() -> Self {
+        SubscriptionMode::Subscribe
+    }
+}
+
+/// Discover nodes by browsing the address space, starting from a root node.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Browse {
+    /// The node ID of the root, which may also use a namespace URI (`nsu=`), or be a browse path.
+    pub root: String,
+    /// The maximum number of levels to browse.
+    #[serde(default = "defaults::browse_depth")]
+    pub depth: u32,
+    /// Only include nodes of those node classes.
+    #[serde(default = "defaults::browse_node_classes")]
+    pub node_classes: Vec<BrowseNodeClass>,
+    /// Only include nodes of those data types (or their subtypes), includes all if empty.
+    #[serde(default)]
+    pub data_types: Vec<String>,
+}
+
+/// The node classes which can be discovered.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum BrowseNodeClass {
+    Object,
+    Variable,
+}
+
+impl From<BrowseNodeClass> for NodeClass {
+    fn from(value: BrowseNodeClass) -> Self {
+        match value {
+            BrowseNodeClass::Object => NodeClass::Object,
+            BrowseNodeClass::Variable => NodeClass::Variable,
+        }
+    }
+}
+
+/// Events to subscribe to.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Events {
+    /// The node emitting the events, defaults to the server object.
+    ///
+    /// Like the event types, this may also use a namespace URI (`nsu=`), or be a browse path.
+    #[serde(default = "defaults::event_source")]
+    pub source: String,
+    /// Additional fields to select, as browse path (e.g. `2:Temperature` or `ShelvingState/Id`).
+    #[serde(default)]
+    pub fields: Vec<String>,
+    /// Only report events of those types (or subtypes), reports all events if empty.
+    #[serde(default)]
+    pub types: Vec<String>,
+    /// Only report events with at least this severity.
+    #[serde(default)]
+    pub min_severity: Option<u16>,
+    #[serde(default = "defaults::event_queue_size")]
+    pub queue_size: u32,
+}
+
+/// A node to monitor.
+///
+/// In the configuration, this can either be a plain node ID, or an object with additional settings.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(from = "NodeEntry")]
+pub struct Node {
+    /// The node ID, which may also use a namespace URI (`nsu=`), or be a browse path.
+    pub id: String,
+    /// An alias, used as feature name and address segment.
+    pub alias: Option<String>,
+    /// Settings overriding the ones from the subscription.
+    pub monitoring: MonitoringSettings,
+    /// Nodes of the same subscription, reported whenever this node reports a change.
+    ///
+    /// Those nodes are referenced by ID or alias, and should use the `Sampling` mode.
+    pub triggered_nodes: Vec<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+enum NodeEntry {
+    Id(String),
+    #[serde(rename_all = "camelCase")]
+    Node {
+        id: String,
+        #[serde(default)]
+        alias: Option<String>,
+        #[serde(flatten)]
+        monitoring: MonitoringSettings,
+        #[serde(default)]
+        triggered_nodes: Vec<String>,
+    },
+}
+
+impl From<NodeEntry> for Node {
+    fn from(entry: NodeEntry) -> Self {
+        match entry {
+            NodeEntry::Id(id) => Self {
+                id,
+                alias: None,
+                monitoring: Default::default(),
+                triggered_nodes: vec![],
+            },
+            NodeEntry::Node {
+                id,
+                alias,
+                monitoring,
+                triggered_nodes,
+            } => Self {
+                id,
+                alias,
+                monitoring,
+                triggered_nodes,
+            },
+        }
+    }
+}
+
+impl Node {
+    /// The name of the node, used as feature name and address segment.
+    ///
+    /// This is the alias, if present, or the normalized node ID otherwise.
+    pub fn name(&self) -> String {
+        match &self.alias {
+            Some(alias) => alias.clone(),
+            None => NodeId::from_str(&self.id)
+                .map(|id| id.to_string())
+                .unwrap_or_else(|_| self.id.clone()),
+        }
+    }
+}
+
+/// Deserialize the nodes of a subscription, rejecting duplicate node IDs and names.
+fn deserialize_nodes<'de, D>(deserializer: D) -> Result<Vec<Node>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let nodes = Vec::<Node>::deserialize(deserializer)?;
+
+    let mut ids = HashSet::new();
+    let mut names = HashSet::new();
+    for node in &nodes {
+        let id = NodeId::from_str(&node.id)
+            .map(|id| id.to_string())
+            .unwrap_or_else(|_| node.id.clone());
+        if !ids.insert(id) {
+            return Err(de::Error::custom(format!("duplicate node ID: {}", node.id)));
+        }
+        let name = node.name();
+        if !names.insert(name.clone()) {
+            return Err(de::Error::custom(format!("duplicate node alias: {}", name)));
+        }
+    }
+
+    Ok(nodes)
+}
+
+/// Settings for monitored items, which can be set per subscription and overridden per node.
+#[derive(Clone, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct MonitoringSettings {
+    #[serde(default)]
+    pub timestamps: Option<Timestamps>,
+    /// The sampling interval, defaults to the publish interval.
+    #[serde(default, with = "humantime_serde")]
+    pub sampling_interval: Option<Duration>,
+    /// The size of the server side queue.
+    #[serde(default)]
+    pub queue_size: Option<u32>,
+    /// Discard the oldest (or otherwise the newest) value when the queue is full.
+    #[serde(default)]
+    pub discard_oldest: Option<bool>,
+    /// The condition reporting a data change, defaults to status and value.
+    #[serde(default)]
+    pub trigger: Option<Trigger>,
+    #[serde(default)]
+    pub deadband: Option<Deadband>,
+    /// Reporting, sampling only (e.g. for triggered items), or disabled.
+    #[serde(default)]
+    pub monitoring_mode: Option<MonitoringMode>,
+}
+
+impl MonitoringSettings {
+    /// Use the values of `self`, falling back to `defaults` for missing ones.
+    pub fn or(&self, defaults: &Self) -> Self {
+        Self {
+            timestamps: self.timestamps.or(defaults.timestamps),
+            sampling_interval: self.sampling_interval.or(defaults.sampling_interval),
+            queue_size: self.queue_size.or(defaults.queue_size),
+            discard_oldest: self.discard_oldest.or(defaults.discard_oldest),
+            trigger: self.trigger.or(defaults.trigger),
+            deadband: self.deadband.or(defaults.deadband),
+            monitoring_mode: self.monitoring_mode.or(defaults.monitoring_mode),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum Trigger {
+    Status,
+    StatusValue,
+    StatusValueTimestamp,
+}
+
+impl Default for Trigger {
+    fn default() -> Self {
+        Trigger::StatusValue
+    }
+}
+
+impl From<Trigger> for DataChangeTrigger {
+    fn from(value: Trigger) -> Self {
+        match value {
+            Trigger::Status => DataChangeTrigger::Status,
+            Trigger::StatusValue => DataChangeTrigger::StatusValue,
+            Trigger::StatusValueTimestamp => DataChangeTrigger::StatusValueTimestamp,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum MonitoringMode {
+    Reporting,
+    Sampling,
+    Disabled,
+}
+
+impl Default for MonitoringMode {
+    fn default() -> Self {
+        MonitoringMode::Reporting
+    }
+}
+
+impl From<MonitoringMode> for crate::opcua::opcua::types::MonitoringMode {
+    fn from(value: MonitoringMode) -> Self {
+        match value {
+            MonitoringMode::Reporting => Self::Reporting,
+            MonitoringMode::Sampling => Self::Sampling,
+            MonitoringMode::Disabled => Self::Disabled,
+        }
+    }
+}
+
+/// A deadband, suppressing value changes below a threshold.
+#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
+pub enum Deadband {
+    /// An absolute value.
+    Absolute(f64),
+    /// A percentage of the node's EURange.
+    Percent(f64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize)]
 pub enum Timestamps {
     None,
     Source,
@@ -73,19 +499,250 @@ impl From<Timestamps> for TimestampsToReturn {
     }
 }
 
+impl Connection {
+    /// All endpoint URLs, starting with the primary one.
+    pub fn urls(&self) -> Vec<&str> {
+        std::iter::once(self.url.as_str())
+            .chain(self.redundant_urls.iter().map(|url| url.as_str()))
+            .collect()
+    }
+
+    /// The session retry limit for the client, `-1` meaning no limit.
+    ///
+    /// With redundant servers, the session must give up eventually, so that the connection can
+    /// fail over to another server.
+    pub fn session_retry_limit(&self) -> i32 {
+        match self.session_retry_limit {
+            Some(limit) => limit as i32,
+            None if !self.redundant_urls.is_empty() => defaults::failover_session_retry_limit(),
+            None => -1,
+        }
+    }
+}
+
+/// Monitoring of the server status and service level.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ServerMonitoring {
+    #[serde(
+        default = "defaults::monitor_server_interval",
+        with = "humantime_serde"
+    )]
+    pub interval: Duration,
+}
+
+/// An exponential backoff, for restarting connections.
+#[derive(Clone, Debug, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Reconnect {
+    #[serde(
+        default = "defaults::reconnect_initial_delay",
+        with = "humantime_serde"
+    )]
+    pub initial_delay: Duration,
+    #[serde(default = "defaults::reconnect_max_delay", with = "humantime_serde")]
+    pub max_delay: Duration,
+    #[serde(default = "defaults::reconnect_multiplier")]
+    pub multiplier: f64,
+    /// Randomly vary the delay by up to this fraction (0 to 1).
+    #[serde(default = "defaults::reconnect_jitter")]
+    pub jitter: f64,
+}
+
+impl Default for Reconnect {
+    fn default() -> Self {
+        Self {
+            initial_delay: defaults::reconnect_initial_delay(),
+            max_delay: defaults::reconnect_max_delay(),
+            multiplier: defaults::reconnect_multiplier(),
+            jitter: defaults::reconnect_jitter(),
+        }
+    }
+}
+
+impl Reconnect {
+    /// The delay before an attempt (starting with 1), `random` being a value between 0 and 1.
+    pub fn delay(&self, attempt: u32, random: f64) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let delay = (self.initial_delay.as_secs_f64() * self.multiplier.max(1.0).powi(exponent))
+            .min(self.max_delay.as_secs_f64());
+
+        let jitter = self.jitter.clamp(0.0, 1.0) * (random.clamp(0.0, 1.0) * 2.0 - 1.0);
+        Duration::from_secs_f64(delay * (1.0 + jitter))
+    }
+}
+
+/// A security policy, ordered from the least to the most secure.
+///
+/// In the configuration, this can either be the name or the URI of the policy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+pub enum SecurityPolicy {
+    #[serde(alias = "http://opcfoundation.org/UA/SecurityPolicy#None")]
+    None,
+    #[serde(alias = "http://opcfoundation.org/UA/SecurityPolicy#Basic128Rsa15")]
+    Basic128Rsa15,
+    #[serde(alias = "http://opcfoundation.org/UA/SecurityPolicy#Basic256")]
+    Basic256,
+    #[serde(alias = "http://opcfoundation.org/UA/SecurityPolicy#Basic256Sha256")]
+    Basic256Sha256,
+    #[serde(alias = "http://opcfoundation.org/UA/SecurityPolicy#Aes128_Sha256_RsaOaep")]
+    Aes128Sha256RsaOaep,
+    #[serde(alias = "http://opcfoundation.org/UA/SecurityPolicy#Aes256_Sha256_RsaPss")]
+    Aes256Sha256RsaPss,
+}
+
+impl Default for SecurityPolicy {
+    fn default() -> Self {
+        SecurityPolicy::None
+    }
+}
+
+impl SecurityPolicy {
+    const ALL: [SecurityPolicy; 6] = [
+        SecurityPolicy::None,
+        SecurityPolicy::Basic128Rsa15,
+        SecurityPolicy::Basic256,
+        SecurityPolicy::Basic256Sha256,
+        SecurityPolicy::Aes128Sha256RsaOaep,
+        SecurityPolicy::Aes256Sha256RsaPss,
+    ];
+
+    pub fn uri(&self) -> &'static str {
+        match self {
+            Self::None => "http://opcfoundation.org/UA/SecurityPolicy#None",
+            Self::Basic128Rsa15 => "http://opcfoundation.org/UA/SecurityPolicy#Basic128Rsa15",
+            Self::Basic256 => "http://opcfoundation.org/UA/SecurityPolicy#Basic256",
+            Self::Basic256Sha256 => "http://opcfoundation.org/UA/SecurityPolicy#Basic256Sha256",
+            Self::Aes128Sha256RsaOaep => {
+                "http://opcfoundation.org/UA/SecurityPolicy#Aes128_Sha256_RsaOaep"
+            }
+            Self::Aes256Sha256RsaPss => {
+                "http://opcfoundation.org/UA/SecurityPolicy#Aes256_Sha256_RsaPss"
+            }
+        }
+    }
+
+    /// Get the security policy of a URI, if it is supported.
+    pub fn from_uri(uri: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|policy| policy.uri() == uri)
+    }
+}
+
+/// A message security mode, ordered from the least to the most secure.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Deserialize)]
+pub enum SecurityMode {
+    #[serde(alias = "none")]
+    None,
+    #[serde(alias = "sign")]
+    Sign,
+    #[serde(alias = "signAndEncrypt")]
+    SignAndEncrypt,
+}
+
+impl From<SecurityMode> for MessageSecurityMode {
+    fn from(value: SecurityMode) -> Self {
+        match value {
+            SecurityMode::None => MessageSecurityMode::None,
+            SecurityMode::Sign => MessageSecurityMode::Sign,
+            SecurityMode::SignAndEncrypt => MessageSecurityMode::SignAndEncrypt,
+        }
+    }
+}
+
+impl TryFrom<MessageSecurityMode> for SecurityMode {
+    type Error = ();
+
+    fn try_from(value: MessageSecurityMode) -> Result<Self, Self::Error> {
+        match value {
+            MessageSecurityMode::None => Ok(SecurityMode::None),
+            MessageSecurityMode::Sign => Ok(SecurityMode::Sign),
+            MessageSecurityMode::SignAndEncrypt => Ok(SecurityMode::SignAndEncrypt),
+            _ => Err(()),
+        }
+    }
+}
+
 mod defaults {
     use std::time::Duration;
 
     pub const fn publish_interval() -> Duration {
         Duration::from_secs(1)
     }
+
+    pub const fn lifetime_count() -> u32 {
+        30
+    }
+
+    pub const fn max_keep_alive_count() -> u32 {
+        10
+    }
+
+    pub const fn publishing_enabled() -> bool {
+        true
+    }
+
+    pub const fn failover_session_retry_limit() -> i32 {
+        3
+    }
+
+    pub fn event_source() -> String {
+        "i=2253".to_string()
+    }
+
+    pub const fn event_queue_size() -> u32 {
+        100
+    }
+
+    pub const fn monitor_server_interval() -> Duration {
+        Duration::from_secs(10)
+    }
+
+    pub const fn reconnect_initial_delay() -> Duration {
+        Duration::from_secs(1)
+    }
+
+    pub const fn reconnect_max_delay() -> Duration {
+        Duration::from_secs(5 * 60)
+    }
+
+    pub const fn reconnect_multiplier() -> f64 {
+        2.0
+    }
+
+    pub const fn reconnect_jitter() -> f64 {
+        0.1
+    }
+
+    pub const fn browse_depth() -> u32 {
+        1
+    }
+
+    pub fn browse_node_classes() -> Vec<super::BrowseNodeClass> {
+        vec![super::BrowseNodeClass::Variable]
+    }
 }
 
 #[derive(Clone, Debug, Deserialize)]
 #[serde(untagged)]
 #[serde(rename_all = "camelCase")]
 pub enum Credentials {
-    User { username: String, password: String },
+    User {
+        username: String,
+        password: String,
+    },
+    /// An X.509 user certificate (DER), and its private key (PEM).
+    #[serde(rename_all = "camelCase")]
+    X509 {
+        user_certificate: PathBuf,
+        user_private_key: PathBuf,
+    },
+    /// An issued token (e.g. from a token service), read from a file.
+    ///
+    /// Not supported by the OPC UA client library yet, connections using it fail to connect.
+    #[serde(rename_all = "camelCase")]
+    IssuedToken {
+        token_file: PathBuf,
+    },
     Anonymous,
 }
 
@@ -102,20 +759,509 @@ mod test {
 
     #[test]
     fn test_cfg() {
-        let _config: Configuration = serde_json::from_value(json!({
+        let config: Connection = serde_json::from_value(json!({
             "url": "opc.tcp://localhost:1234",
-            "subscriptions": [
-                {
+            "securityPolicy": "None",
+            "securityMode": "None",
+            "subscriptions": {
+                "default": {
                     "nodes": [
-                        "ns=1,s=Foo",
+                        "ns=1;s=Foo",
                         {
-                            "id": "ns=1,s=Bar",
+                            "id": "ns=1;s=Bar",
                             "alias": "bar",
+                            "timestamps": "Both",
                         }
                     ]
                 }
+            }
+        }))
+        .unwrap();
+
+        let nodes = &config.subscriptions["default"].nodes;
+        assert_eq!(
+            nodes,
+            &vec![
+                Node {
+                    id: "ns=1;s=Foo".to_string(),
+                    alias: None,
+                    monitoring: Default::default(),
+                    triggered_nodes: vec![],
+                },
+                Node {
+                    id: "ns=1;s=Bar".to_string(),
+                    alias: Some("bar".to_string()),
+                    monitoring: MonitoringSettings {
+                        timestamps: Some(Timestamps::Both),
+                        ..Default::default()
+                    },
+                    triggered_nodes: vec![],
+                }
+            ]
+        );
+
+        assert_eq!(nodes[0].name(), "ns=1;s=Foo");
+        assert_eq!(nodes[1].name(), "bar");
+
+        assert!(!config.decode_structures);
+        assert_eq!(config.json_encoding, JsonEncoding::Simple);
+    }
+
+    #[test]
+    fn test_cfg_duplicates() {
+        let subscription: Result<Subscription, _> = serde_json::from_value(json!({
+            "nodes": [
+                "ns=1;s=Foo",
+                { "id": "ns=1;s=Foo", "alias": "foo" },
             ]
+        }));
+        assert!(subscription.is_err());
+
+        let subscription: Result<Subscription, _> = serde_json::from_value(json!({
+            "nodes": [
+                { "id": "ns=1;s=Foo", "alias": "foo" },
+                { "id": "ns=1;s=Bar", "alias": "foo" },
+            ]
+        }));
+        assert!(subscription.is_err());
+
+        // an alias must not clash with the ID of another node either
+        let subscription: Result<Subscription, _> = serde_json::from_value(json!({
+            "nodes": [
+                "ns=1;s=Foo",
+                { "id": "ns=1;s=Bar", "alias": "ns=1;s=Foo" },
+            ]
+        }));
+        assert!(subscription.is_err());
+
+        let subscription: Subscription = serde_json::from_value(json!({
+            "nodes": [
+                "ns=1;s=Foo",
+                { "id": "ns=1;s=Bar", "alias": "foo" },
+            ]
+        }))
+        .unwrap();
+        assert_eq!(subscription.nodes.len(), 2);
+    }
+
+    #[test]
+    fn test_cfg_monitoring() {
+        let subscription: Subscription = serde_json::from_value(json!({
+            "samplingInterval": "5s",
+            "queueSize": 10,
+            "nodes": [
+                "ns=1;s=Foo",
+                {
+                    "id": "ns=1;s=Bar",
+                    "samplingInterval": "100ms",
+                    "discardOldest": false,
+                }
+            ]
+        }))
+        .unwrap();
+
+        assert_eq!(
+            subscription.nodes[0]
+                .monitoring
+                .or(&subscription.monitoring),
+            MonitoringSettings {
+                sampling_interval: Some(Duration::from_secs(5)),
+                queue_size: Some(10),
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            subscription.nodes[1]
+                .monitoring
+                .or(&subscription.monitoring),
+            MonitoringSettings {
+                sampling_interval: Some(Duration::from_millis(100)),
+                queue_size: Some(10),
+                discard_oldest: Some(false),
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn test_cfg_events() {
+        let subscription: Subscription = serde_json::from_value(json!({
+            "events": {
+                "types": ["i=2915"],
+                "minSeverity": 500,
+            }
+        }))
+        .unwrap();
+
+        assert!(subscription.nodes.is_empty());
+        assert_eq!(
+            subscription.events,
+            Some(Events {
+                source: "i=2253".to_string(),
+                fields: vec![],
+                types: vec!["i=2915".to_string()],
+                min_severity: Some(500),
+                queue_size: 100,
+            })
+        );
+    }
+
+    #[test]
+    fn test_cfg_browse() {
+        let subscription: Subscription = serde_json::from_value(json!({
+            "browse": {
+                "root": "ns=2;s=Line1",
+                "depth": 3,
+                "dataTypes": ["i=11"],
+            }
+        }))
+        .unwrap();
+
+        assert_eq!(
+            subscription.browse,
+            Some(Browse {
+                root: "ns=2;s=Line1".to_string(),
+                depth: 3,
+                node_classes: vec![BrowseNodeClass::Variable],
+                data_types: vec!["i=11".to_string()],
+            })
+        );
+    }
+
+    #[test]
+    fn test_cfg_deadband() {
+        let subscription: Subscription = serde_json::from_value(json!({
+            "trigger": "StatusValueTimestamp",
+            "deadband": { "Absolute": 0.5 },
+            "nodes": [
+                "ns=1;s=Foo",
+                {
+                    "id": "ns=1;s=Bar",
+                    "deadband": { "Percent": 2.0 },
+                }
+            ]
+        }))
+        .unwrap();
+
+        let foo = subscription.nodes[0]
+            .monitoring
+            .or(&subscription.monitoring);
+        assert_eq!(foo.trigger, Some(Trigger::StatusValueTimestamp));
+        assert_eq!(foo.deadband, Some(Deadband::Absolute(0.5)));
+
+        let bar = subscription.nodes[1]
+            .monitoring
+            .or(&subscription.monitoring);
+        assert_eq!(bar.trigger, Some(Trigger::StatusValueTimestamp));
+        assert_eq!(bar.deadband, Some(Deadband::Percent(2.0)));
+    }
+
+    #[test]
+    fn test_cfg_backfill() {
+        let subscription: Subscription = serde_json::from_value(json!({
+            "nodes": ["ns=1;s=Foo"],
+        }))
+        .unwrap();
+        assert!(!subscription.backfill);
+
+        let subscription: Subscription = serde_json::from_value(json!({
+            "nodes": ["ns=1;s=Foo"],
+            "backfill": true,
+        }))
+        .unwrap();
+        assert!(subscription.backfill);
+    }
+
+    #[test]
+    fn test_cfg_poll() {
+        let subscription: Subscription = serde_json::from_value(json!({
+            "nodes": ["ns=1;s=Foo"],
+        }))
+        .unwrap();
+        assert_eq!(subscription.mode, SubscriptionMode::Subscribe);
+
+        let subscription: Subscription = serde_json::from_value(json!({
+            "publishInterval": "5s",
+            "mode": "poll",
+            "suppressUnchanged": true,
+            "nodes": ["ns=1;s=Foo"],
+        }))
+        .unwrap();
+        assert_eq!(subscription.mode, SubscriptionMode::Poll);
+        assert_eq!(subscription.publish_interval, Duration::from_secs(5));
+        assert!(subscription.suppress_unchanged);
+    }
+
+    #[test]
+    fn test_cfg_pki() {
+        let config: Connection = serde_json::from_value(json!({
+            "url": "opc.tcp://localhost:1234",
+            "securityPolicy": "Basic256Sha256",
+            "securityMode": "SignAndEncrypt",
+            "applicationUri": "urn:example:agent",
+            "clientCertificate": "own/cert.der",
+            "clientPrivateKey": "private/private.pem",
+            "trustedServerCertificates": ["/etc/opcua/server.der"],
+        }))
+        .unwrap();
+
+        assert_eq!(config.application_uri.as_deref(), Some("urn:example:agent"));
+        assert_eq!(config.pki_dir, None);
+        assert_eq!(
+            config.client_certificate,
+            Some(PathBuf::from("own/cert.der"))
+        );
+        assert_eq!(
+            config.client_private_key,
+            Some(PathBuf::from("private/private.pem"))
+        );
+        assert_eq!(
+            config.trusted_server_certificates,
+            vec![PathBuf::from("/etc/opcua/server.der")]
+        );
+        assert_eq!(config.server_certificate_thumbprint, None);
+    }
+
+    #[test]
+    fn test_cfg_credentials() {
+        let credentials: Credentials = serde_json::from_value(json!({
+            "username": "foo",
+            "password": "bar",
+        }))
+        .unwrap();
+        assert!(matches!(credentials, Credentials::User { .. }));
+
+        let credentials: Credentials = serde_json::from_value(json!({
+            "userCertificate": "user.der",
+            "userPrivateKey": "user.pem",
+        }))
+        .unwrap();
+        assert!(matches!(
+            credentials,
+            Credentials::X509 { user_certificate, user_private_key }
+                if user_certificate == PathBuf::from("user.der")
+                    && user_private_key == PathBuf::from("user.pem")
+        ));
+
+        let credentials: Credentials = serde_json::from_value(json!({
+            "tokenFile": "/run/secrets/token",
+        }))
+        .unwrap();
+        assert!(matches!(
+            credentials,
+            Credentials::IssuedToken { token_file }
+                if token_file == PathBuf::from("/run/secrets/token")
+        ));
+    }
+
+    #[test]
+    fn test_cfg_security() {
+        let config: Connection = serde_json::from_value(json!({
+            "url": "opc.tcp://localhost:1234",
+            "securityMode": "signAndEncrypt",
+            "minSecurityPolicy": "Basic256Sha256",
+        }))
+        .unwrap();
+
+        assert_eq!(config.security_policy, None);
+        assert_eq!(config.security_mode, Some(SecurityMode::SignAndEncrypt));
+        assert_eq!(config.min_security_policy, SecurityPolicy::Basic256Sha256);
+
+        let config: Result<Connection, _> = serde_json::from_value(json!({
+            "url": "opc.tcp://localhost:1234",
+            "securityPolicy": "Basic265Sha256",
+        }));
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn test_security_policy_uri() {
+        for policy in SecurityPolicy::ALL {
+            assert_eq!(SecurityPolicy::from_uri(policy.uri()), Some(policy));
+            assert_eq!(
+                serde_json::from_value::<SecurityPolicy>(json!(policy.uri())).unwrap(),
+                policy
+            );
+        }
+        assert_eq!(SecurityPolicy::from_uri("urn:foo"), None);
+    }
+
+    #[test]
+    fn test_reconnect_delay() {
+        let reconnect: Reconnect = serde_json::from_value(json!({
+            "initialDelay": "1s",
+            "maxDelay": "1m",
+        }))
+        .unwrap();
+
+        assert_eq!(reconnect.multiplier, 2.0);
+        assert_eq!(reconnect.jitter, 0.1);
+
+        assert_eq!(reconnect.delay(1, 0.5), Duration::from_secs(1));
+        assert_eq!(reconnect.delay(2, 0.5), Duration::from_secs(2));
+        assert_eq!(reconnect.delay(5, 0.5), Duration::from_secs(16));
+        assert_eq!(reconnect.delay(10, 0.5), Duration::from_secs(60));
+        assert_eq!(reconnect.delay(1000, 0.5), Duration::from_secs(60));
+
+        // jitter
+        assert_eq!(reconnect.delay(2, 0.0), Duration::from_secs_f64(1.8));
+        assert_eq!(reconnect.delay(2, 1.0), Duration::from_secs_f64(2.2));
+    }
+
+    #[test]
+    fn test_cfg_redundant() {
+        let config: Connection = serde_json::from_value(json!({
+            "url": "opc.tcp://server1:4840",
+            "redundantUrls": ["opc.tcp://server2:4840"],
+            "checkServiceLevel": true,
+        }))
+        .unwrap();
+
+        assert_eq!(
+            config.urls(),
+            vec!["opc.tcp://server1:4840", "opc.tcp://server2:4840"]
+        );
+        assert!(config.check_service_level);
+    }
+
+    #[test]
+    fn test_cfg_session_retry_limit() {
+        let config: Connection = serde_json::from_value(json!({
+            "url": "opc.tcp://server1:4840",
+        }))
+        .unwrap();
+        assert_eq!(config.session_retry_limit(), -1);
+
+        // fail over eventually
+        let config: Connection = serde_json::from_value(json!({
+            "url": "opc.tcp://server1:4840",
+            "redundantUrls": ["opc.tcp://server2:4840"],
+        }))
+        .unwrap();
+        assert_eq!(config.session_retry_limit(), 3);
+
+        let config: Connection = serde_json::from_value(json!({
+            "url": "opc.tcp://server1:4840",
+            "redundantUrls": ["opc.tcp://server2:4840"],
+            "sessionRetryLimit": 10,
+        }))
+        .unwrap();
+        assert_eq!(config.session_retry_limit(), 10);
+    }
+
+    #[test]
+    fn test_cfg_monitor_server() {
+        let config: Connection = serde_json::from_value(json!({
+            "url": "opc.tcp://localhost:4840",
+            "monitorServer": {},
+        }))
+        .unwrap();
+
+        assert_eq!(
+            config.monitor_server,
+            Some(ServerMonitoring {
+                interval: Duration::from_secs(10)
+            })
+        );
+    }
+
+    #[test]
+    fn test_cfg_tuning() {
+        let subscription: Subscription = serde_json::from_value(json!({
+            "nodes": ["ns=1;s=Foo"],
+        }))
+        .unwrap();
+        assert_eq!(subscription.lifetime_count, 30);
+        assert_eq!(subscription.max_keep_alive_count, 10);
+        assert_eq!(subscription.validate(), Ok(()));
+        assert_eq!(subscription.max_notifications_per_publish, 0);
+        assert_eq!(subscription.priority, 0);
+        assert!(subscription.publishing_enabled);
+
+        let subscription: Subscription = serde_json::from_value(json!({
+            "publishInterval": "1m",
+            "lifetimeCount": 300,
+            "maxKeepAliveCount": 10,
+            "maxNotificationsPerPublish": 1000,
+            "priority": 5,
+            "nodes": [
+                {
+                    "id": "ns=1;s=Trigger",
+                    "triggeredNodes": ["ns=1;s=Foo", "bar"],
+                },
+                {
+                    "id": "ns=1;s=Foo",
+                    "monitoringMode": "Sampling",
+                },
+            ]
+        }))
+        .unwrap();
+        assert_eq!(subscription.lifetime_count, 300);
+        assert_eq!(subscription.max_keep_alive_count, 10);
+        assert_eq!(subscription.max_notifications_per_publish, 1000);
+        assert_eq!(subscription.priority, 5);
+        assert_eq!(
+            subscription.nodes[0].triggered_nodes,
+            vec!["ns=1;s=Foo".to_string(), "bar".to_string()]
+        );
+        assert_eq!(
+            subscription.nodes[1].monitoring.monitoring_mode,
+            Some(MonitoringMode::Sampling)
+        );
+    }
+
+    #[test]
+    fn test_cfg_lifetime_count() {
+        let config: Result<Connection, _> = serde_json::from_value(json!({
+            "url": "opc.tcp://localhost:4840",
+            "subscriptions": {
+                "default": {
+                    "lifetimeCount": 20,
+                    "maxKeepAliveCount": 10,
+                    "nodes": ["ns=1;s=Foo"],
+                }
+            }
+        }));
+        assert!(config.is_err());
+
+        let config: Result<Connection, _> = serde_json::from_value(json!({
+            "url": "opc.tcp://localhost:4840",
+            "subscriptions": {
+                "default": {
+                    "lifetimeCount": 30,
+                    "maxKeepAliveCount": 10,
+                    "nodes": ["ns=1;s=Foo"],
+                }
+            }
+        }));
+        assert!(config.is_ok());
+    }
+
+    #[test]
+    fn test_cfg_retry() {
+        let subscription: Subscription = serde_json::from_value(json!({
+            "nodes": ["ns=1;s=Foo"],
+        }))
+        .unwrap();
+        assert_eq!(subscription.retry_interval, None);
+
+        let subscription: Subscription = serde_json::from_value(json!({
+            "nodes": ["ns=1;s=Foo"],
+            "retryInterval": "30s",
+        }))
+        .unwrap();
+        assert_eq!(subscription.retry_interval, Some(Duration::from_secs(30)));
+    }
+
+    #[test]
+    fn test_cfg_json_encoding() {
+        let config: Connection = serde_json::from_value(json!({
+            "url": "opc.tcp://localhost:1234",
+            "securityPolicy": "None",
+            "securityMode": "None",
+            "jsonEncoding": "nonReversible",
         }))
         .unwrap();
+        assert_eq!(config.json_encoding, JsonEncoding::NonReversible);
     }
 }
@@ -0,0 +1,367 @@
+//! Parsing of the legacy data type dictionaries, using the OPC Binary type system.
+//!
+//! Servers before OPC UA 1.04 don't provide the `DataTypeDefinition` attribute. Instead, they
+//! describe the binary encoding of their structures with a dictionary, as defined by part 5 of
+//! the specification.
+
+use super::opcua::client::prelude::*;
+use roxmltree::Node;
+use std::collections::{HashMap, HashSet};
+
+/// The namespace of the OPC Binary schema, defining the primitive types.
+const BINARY_SCHEMA: &str = "http://opcfoundation.org/BinarySchema/";
+/// The namespace of OPC UA, defining the other built-in types.
+const UA: &str = "http://opcfoundation.org/UA/";
+
+/// The type of a field.
+#[derive(Clone, Debug, PartialEq)]
+pub enum TypeName {
+    /// A built-in type, by its built-in type ID.
+    BuiltIn(u32),
+    /// A type of the same dictionary, by its name.
+    Local(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Field {
+    pub name: String,
+    pub type_name: TypeName,
+    pub is_array: bool,
+    pub is_optional: bool,
+}
+
+/// A type of a dictionary.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Description {
+    Structure {
+        structure_type: StructureType,
+        fields: Vec<Field>,
+    },
+    Enumeration,
+}
+
+/// The types of a dictionary, by their name.
+pub type Dictionary = HashMap<String, Description>;
+
+/// Parse a dictionary.
+///
+/// Types which can't be decoded are logged and skipped, so that the other types can be used.
+pub fn parse(xml: &str) -> Result<Dictionary, StatusCode> {
+    let document = roxmltree::Document::parse(xml).map_err(|err| {
+        log::info!("Failed to parse type dictionary: {err}");
+        StatusCode::BadDecodingError
+    })?;
+
+    let root = document.root_element();
+    let target_namespace = root.attribute("TargetNamespace").unwrap_or_default();
+
+    let mut dictionary = Dictionary::new();
+    for node in root.children().filter(Node::is_element) {
+        let name = match node.attribute("Name") {
+            Some(name) => name,
+            None => continue,
+        };
+
+        let description = match node.tag_name().name() {
+            "StructuredType" => structure(node, target_namespace),
+            // encoded as Int32, like enumerations with a definition
+            "EnumeratedType" => Ok(Description::Enumeration),
+            // opaque types are encoded as the server sees fit
+            _ => continue,
+        };
+
+        match description {
+            Ok(description) => {
+                dictionary.insert(name.to_string(), description);
+            }
+            Err(status) => log::info!("Unsupported type {name} in type dictionary: {status}"),
+        }
+    }
+
+    Ok(dictionary)
+}
+
+fn structure(node: Node, target_namespace: &str) -> Result<Description, StatusCode> {
+    let elements = node
+        .children()
+        .filter(|child| child.is_element() && child.tag_name().name() == "Field")
+        .collect::<Vec<_>>();
+
+    // fields holding the length of an array, decoded along with the array
+    let lengths = elements
+        .iter()
+        .filter_map(|element| element.attribute("LengthField"))
+        .collect::<HashSet<_>>();
+    // fields holding the selected field of a union
+    let switches = elements
+        .iter()
+        .filter(|element| element.attribute("SwitchValue").is_some())
+        .filter_map(|element| element.attribute("SwitchField"))
+        .collect::<HashSet<_>>();
+
+    let mut structure_type = StructureType::Structure;
+    // bits of the encoding mask, by name
+    let mut bits = HashMap::new();
+    let mut position = 0;
+    let mut fields = Vec::<Field>::new();
+
+    for (index, element) in elements.into_iter().enumerate() {
+        let name = element
+            .attribute("Name")
+            .ok_or(StatusCode::BadDecodingError)?;
+        let (namespace, local) = qualified(
+            element,
+            element
+                .attribute("TypeName")
+                .ok_or(StatusCode::BadDecodingError)?,
+        );
+
+        if namespace == Some(BINARY_SCHEMA) && local == "Bit" {
+            // the encoding mask must come first, as it is decoded upfront
+            if bits.len() != index {
+                return Err(StatusCode::BadNotSupported);
+            }
+            let length = match element.attribute("Length") {
+                Some(length) => length.parse().map_err(|_| StatusCode::BadDecodingError)?,
+                None => 1,
+            };
+            bits.insert(name, position);
+            position += length;
+            continue;
+        }
+
+        if switches.contains(name) {
+            // the switch must come first, and be decoded as UInt32
+            if index != 0 || namespace != Some(BINARY_SCHEMA) || local != "UInt32" {
+                return Err(StatusCode::BadNotSupported);
+            }
+            structure_type = StructureType::Union;
+            continue;
+        }
+
+        if lengths.contains(name) {
+            continue;
+        }
+
+        let union = structure_type == StructureType::Union;
+        let is_optional = match (element.attribute("SwitchField"), union) {
+            (Some(switch), true) => {
+                // the switch selects fields by their position, starting with 1
+                let value = (fields.len() + 1).to_string();
+                if !switches.contains(switch)
+                    || element.attribute("SwitchValue") != Some(value.as_str())
+                {
+                    return Err(StatusCode::BadNotSupported);
+                }
+                false
+            }
+            (Some(switch), false) => {
+                // the encoding mask has a bit per optional field, in order
+                let optional = fields.iter().filter(|field| field.is_optional).count();
+                if bits.get(switch) != Some(&optional) {
+                    return Err(StatusCode::BadNotSupported);
+                }
+                structure_type = StructureType::StructureWithOptionalFields;
+                true
+            }
+            (None, true) => return Err(StatusCode::BadNotSupported),
+            (None, false) => false,
+        };
+
+        fields.push(Field {
+            name: name.to_string(),
+            type_name: type_name(namespace, local, target_namespace)?,
+            is_array: element.attribute("LengthField").is_some(),
+            is_optional,
+        });
+    }
+
+    // the encoding mask is decoded as UInt32
+    if !bits.is_empty() && position != 32 {
+        return Err(StatusCode::BadNotSupported);
+    }
+
+    Ok(Description::Structure {
+        structure_type,
+        fields,
+    })
+}
+
+/// Split a qualified name into its namespace and local name.
+fn qualified<'a>(node: Node<'a, '_>, name: &'a str) -> (Option<&'a str>, &'a str) {
+    match name.split_once(':') {
+        Some((prefix, local)) => (node.lookup_namespace_uri(Some(prefix)), local),
+        None => (node.lookup_namespace_uri(None), name),
+    }
+}
+
+fn type_name(
+    namespace: Option<&str>,
+    local: &str,
+    target_namespace: &str,
+) -> Result<TypeName, StatusCode> {
+    match namespace {
+        Some(BINARY_SCHEMA) | Some(UA) => built_in(local)
+            .map(TypeName::BuiltIn)
+            .ok_or(StatusCode::BadDataTypeIdUnknown),
+        Some(namespace) if namespace == target_namespace => Ok(TypeName::Local(local.to_string())),
+        // types of other dictionaries
+        _ => Err(StatusCode::BadDataTypeIdUnknown),
+    }
+}
+
+/// Get the built-in type ID of a type of the OPC Binary schema, or of OPC UA.
+fn built_in(name: &str) -> Option<u32> {
+    Some(match name {
+        "Boolean" => 1,
+        "SByte" => 2,
+        "Byte" => 3,
+        "Int16" => 4,
+        "UInt16" => 5,
+        "Int32" => 6,
+        "UInt32" => 7,
+        "Int64" => 8,
+        "UInt64" => 9,
+        "Float" => 10,
+        "Double" => 11,
+        // character arrays are encoded like strings
+        "String" | "CharArray" => 12,
+        "DateTime" => 13,
+        "Guid" => 14,
+        "ByteString" => 15,
+        "XmlElement" => 16,
+        "NodeId" => 17,
+        "ExpandedNodeId" => 18,
+        "StatusCode" => 19,
+        "QualifiedName" => 20,
+        "LocalizedText" => 21,
+        "ExtensionObject" => 22,
+        "DataValue" => 23,
+        "Variant" => 24,
+        "DiagnosticInfo" => 25,
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const DICTIONARY: &str = r#"<opc:TypeDictionary
+    xmlns:opc="http://opcfoundation.org/BinarySchema/"
+    xmlns:ua="http://opcfoundation.org/UA/"
+    xmlns:tns="urn:example"
+    DefaultByteOrder="LittleEndian"
+    TargetNamespace="urn:example">
+  <opc:Import Namespace="http://opcfoundation.org/UA/"/>
+  <opc:EnumeratedType Name="Mode" LengthInBits="32">
+    <opc:EnumeratedValue Name="Off" Value="0"/>
+    <opc:EnumeratedValue Name="On" Value="1"/>
+  </opc:EnumeratedType>
+  <opc:StructuredType Name="Point" BaseType="ua:ExtensionObject">
+    <opc:Field Name="X" TypeName="opc:Double"/>
+    <opc:Field Name="Label" TypeName="opc:CharArray"/>
+    <opc:Field Name="NoOfTags" TypeName="opc:Int32"/>
+    <opc:Field Name="Tags" TypeName="ua:LocalizedText" LengthField="NoOfTags"/>
+    <opc:Field Name="Mode" TypeName="tns:Mode"/>
+  </opc:StructuredType>
+  <opc:StructuredType Name="Sample" BaseType="ua:ExtensionObject">
+    <opc:Field Name="PointSpecified" TypeName="opc:Bit"/>
+    <opc:Field Name="Reserved1" TypeName="opc:Bit" Length="31"/>
+    <opc:Field Name="Value" TypeName="opc:Int32"/>
+    <opc:Field Name="Point" TypeName="tns:Point" SwitchField="PointSpecified"/>
+  </opc:StructuredType>
+  <opc:StructuredType Name="Choice" BaseType="ua:Union">
+    <opc:Field Name="SwitchField" TypeName="opc:UInt32"/>
+    <opc:Field Name="Number" TypeName="opc:Int32" SwitchField="SwitchField" SwitchValue="1"/>
+    <opc:Field Name="Text" TypeName="opc:String" SwitchField="SwitchField" SwitchValue="2"/>
+  </opc:StructuredType>
+  <opc:StructuredType Name="Imported" BaseType="ua:ExtensionObject">
+    <opc:Field Name="Range" TypeName="ua:Range"/>
+  </opc:StructuredType>
+  <opc:OpaqueType Name="Blob"/>
+</opc:TypeDictionary>"#;
+
+    fn field(name: &str, type_name: TypeName) -> Field {
+        Field {
+            name: name.to_string(),
+            type_name,
+            is_array: false,
+            is_optional: false,
+        }
+    }
+
+    #[test]
+    fn test_parse() {
+        let dictionary = parse(DICTIONARY).unwrap();
+
+        assert_eq!(dictionary.get("Mode"), Some(&Description::Enumeration));
+        assert_eq!(
+            dictionary.get("Point"),
+            Some(&Description::Structure {
+                structure_type: StructureType::Structure,
+                fields: vec![
+                    field("X", TypeName::BuiltIn(11)),
+                    field("Label", TypeName::BuiltIn(12)),
+                    Field {
+                        is_array: true,
+                        ..field("Tags", TypeName::BuiltIn(21))
+                    },
+                    field("Mode", TypeName::Local("Mode".to_string())),
+                ],
+            })
+        );
+        assert_eq!(
+            dictionary.get("Sample"),
+            Some(&Description::Structure {
+                structure_type: StructureType::StructureWithOptionalFields,
+                fields: vec![
+                    field("Value", TypeName::BuiltIn(6)),
+                    Field {
+                        is_optional: true,
+                        ..field("Point", TypeName::Local("Point".to_string()))
+                    },
+                ],
+            })
+        );
+        assert_eq!(
+            dictionary.get("Choice"),
+            Some(&Description::Structure {
+                structure_type: StructureType::Union,
+                fields: vec![
+                    field("Number", TypeName::BuiltIn(6)),
+                    field("Text", TypeName::BuiltIn(12)),
+                ],
+            })
+        );
+
+        // structures of other dictionaries aren't known, opaque types are skipped
+        assert_eq!(dictionary.get("Imported"), None);
+        assert_eq!(dictionary.get("Blob"), None);
+    }
+
+    #[test]
+    fn test_parse_unsupported_mask() {
+        let dictionary = parse(
+            r#"<opc:TypeDictionary xmlns:opc="http://opcfoundation.org/BinarySchema/" TargetNamespace="urn:example">
+  <opc:StructuredType Name="Short">
+    <opc:Field Name="ValueSpecified" TypeName="opc:Bit"/>
+    <opc:Field Name="Reserved1" TypeName="opc:Bit" Length="7"/>
+    <opc:Field Name="Value" TypeName="opc:Int32" SwitchField="ValueSpecified"/>
+  </opc:StructuredType>
+</opc:TypeDictionary>"#,
+        )
+        .unwrap();
+
+        // the encoding mask is a single byte only
+        assert!(dictionary.is_empty());
+    }
+
+    #[test]
+    fn test_parse_invalid() {
+        assert_eq!(
+            parse("<opc:TypeDictionary"),
+            Err(StatusCode::BadDecodingError)
+        );
+    }
+}
@@ -0,0 +1,172 @@
+//! Selection of the endpoint to connect to.
+
+use super::{opcua::client::prelude::*, Connection, SecurityMode, SecurityPolicy};
+use anyhow::bail;
+
+/// The security settings of an endpoint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Endpoint {
+    pub security_policy: SecurityPolicy,
+    pub security_mode: SecurityMode,
+    /// The relative security level, as assigned by the server.
+    pub security_level: u8,
+}
+
+impl Endpoint {
+    fn from_description(endpoint: &EndpointDescription) -> Option<Self> {
+        Some(Self {
+            security_policy: SecurityPolicy::from_uri(endpoint.security_policy_uri.as_ref())?,
+            security_mode: SecurityMode::try_from(endpoint.security_mode).ok()?,
+            security_level: endpoint.security_level,
+        })
+    }
+}
+
+/// Find the endpoint to connect to.
+///
+/// If both the security policy and mode are configured, those are used as-is. Otherwise, the
+/// server's endpoints are retrieved, and the most secure one matching the configuration is used.
+pub fn select(client: &Client, url: &str, config: &Connection) -> anyhow::Result<Endpoint> {
+    if let Some(security_policy) = config.security_policy {
+        if security_policy < config.min_security_policy {
+            bail!("The security policy is below the minimum security policy");
+        }
+    }
+
+    if let (Some(security_policy), Some(security_mode)) =
+        (config.security_policy, config.security_mode)
+    {
+        return Ok(Endpoint {
+            security_policy,
+            security_mode,
+            security_level: 0,
+        });
+    }
+
+    let endpoints = client
+        .get_server_endpoints_from_url(url)?
+        .iter()
+        .filter_map(Endpoint::from_description)
+        .collect::<Vec<_>>();
+
+    match most_secure(&endpoints, config) {
+        Some(endpoint) => Ok(endpoint),
+        None => bail!(
+            "None of the {} supported endpoints of {} matches the security requirements",
+            endpoints.len(),
+            url
+        ),
+    }
+}
+
+/// Find the most secure endpoint, matching the configuration.
+///
+/// Endpoints are ranked by the security level the server assigned, then by the security mode,
+/// and last by the security policy.
+fn most_secure(endpoints: &[Endpoint], config: &Connection) -> Option<Endpoint> {
+    endpoints
+        .iter()
+        .filter(|endpoint| endpoint.security_policy >= config.min_security_policy)
+        .filter(|endpoint| match config.security_policy {
+            Some(security_policy) => endpoint.security_policy == security_policy,
+            None => true,
+        })
+        .filter(|endpoint| match config.security_mode {
+            Some(security_mode) => endpoint.security_mode == security_mode,
+            None => true,
+        })
+        .max_by_key(|endpoint| {
+            (
+                endpoint.security_level,
+                endpoint.security_mode,
+                endpoint.security_policy,
+            )
+        })
+        .copied()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn endpoint(security_policy: SecurityPolicy, security_mode: SecurityMode) -> Endpoint {
+        Endpoint {
+            security_policy,
+            security_mode,
+            security_level: 0,
+        }
+    }
+
+    fn config(config: serde_json::Value) -> Connection {
+        let mut value = json!({ "url": "opc.tcp://localhost:4840" });
+        if let (Some(value), serde_json::Value::Object(config)) = (value.as_object_mut(), config) {
+            value.extend(config);
+        }
+        serde_json::from_value(value).unwrap()
+    }
+
+    #[test]
+    fn test_most_secure() {
+        let endpoints = [
+            endpoint(SecurityPolicy::None, SecurityMode::None),
+            endpoint(SecurityPolicy::Basic256Sha256, SecurityMode::Sign),
+            endpoint(SecurityPolicy::Basic256Sha256, SecurityMode::SignAndEncrypt),
+            endpoint(SecurityPolicy::Basic128Rsa15, SecurityMode::SignAndEncrypt),
+        ];
+
+        assert_eq!(
+            most_secure(&endpoints, &config(json!({}))),
+            Some(endpoint(
+                SecurityPolicy::Basic256Sha256,
+                SecurityMode::SignAndEncrypt
+            ))
+        );
+        assert_eq!(
+            most_secure(&endpoints, &config(json!({"securityMode": "Sign"}))),
+            Some(endpoint(SecurityPolicy::Basic256Sha256, SecurityMode::Sign))
+        );
+        assert_eq!(
+            most_secure(
+                &endpoints,
+                &config(json!({"securityPolicy": "Basic128Rsa15"}))
+            ),
+            Some(endpoint(
+                SecurityPolicy::Basic128Rsa15,
+                SecurityMode::SignAndEncrypt
+            ))
+        );
+        assert_eq!(
+            most_secure(
+                &endpoints,
+                &config(json!({"minSecurityPolicy": "Aes256Sha256RsaPss"}))
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn test_most_secure_ranking() {
+        // the mode ranks before the policy
+        let endpoints = [
+            endpoint(SecurityPolicy::Aes256Sha256RsaPss, SecurityMode::Sign),
+            endpoint(SecurityPolicy::Basic256Sha256, SecurityMode::SignAndEncrypt),
+        ];
+        assert_eq!(
+            most_secure(&endpoints, &config(json!({}))),
+            Some(endpoints[1])
+        );
+
+        // the level assigned by the server ranks first
+        let mut preferred = endpoint(SecurityPolicy::Basic256Sha256, SecurityMode::Sign);
+        preferred.security_level = 10;
+        let endpoints = [
+            endpoint(
+                SecurityPolicy::Aes256Sha256RsaPss,
+                SecurityMode::SignAndEncrypt,
+            ),
+            preferred,
+        ];
+        assert_eq!(most_secure(&endpoints, &config(json!({}))), Some(preferred));
+    }
+}
@@ -0,0 +1,320 @@
+//! Support for event (and alarm) subscriptions.
+
+use super::{opcua::client::prelude::*, structures::DataTypes, Events};
+use crate::ToJson;
+use serde_json::{Map, Value};
+
+/// Fields selected from every event, as browse paths relative to the `BaseEventType`.
+const BASE_FIELDS: &[&str] = &[
+    "EventId",
+    "EventType",
+    "SourceNode",
+    "SourceName",
+    "Time",
+    "ReceiveTime",
+    "Message",
+    "Severity",
+];
+
+/// Fields selected from conditions, as browse paths relative to the `ConditionType`.
+///
+/// Events which are not conditions report those as null.
+const CONDITION_FIELDS: &[&str] = &[
+    "ConditionName",
+    "BranchId",
+    "Retain",
+    "EnabledState/Id",
+    "Quality",
+    "LastSeverity",
+    "Comment",
+    "AckedState/Id",
+    "ConfirmedState/Id",
+    "ActiveState/Id",
+];
+
+/// The name of the field carrying the condition ID.
+const CONDITION_ID: &str = "ConditionId";
+
+/// The event filter of an events configuration.
+pub struct Filter {
+    /// The names of the selected fields, in the order of the select clauses.
+    pub fields: Vec<String>,
+    pub filter: EventFilter,
+}
+
+impl Filter {
+    /// Create the filter, with the resolved node IDs of the event types.
+    pub fn new(events: &Events, types: &[NodeId]) -> Self {
+        let mut fields = vec![];
+        let mut select_clauses = vec![];
+
+        let base_fields = BASE_FIELDS
+            .iter()
+            .map(|f| (ObjectTypeId::BaseEventType, f.to_string()));
+        let condition_fields = CONDITION_FIELDS
+            .iter()
+            .map(|f| (ObjectTypeId::ConditionType, f.to_string()));
+        let custom_fields = events
+            .fields
+            .iter()
+            .map(|f| (ObjectTypeId::BaseEventType, f.clone()));
+
+        for (type_id, field) in base_fields.chain(condition_fields).chain(custom_fields) {
+            select_clauses.push(simple_attribute(type_id, &field, AttributeId::Value));
+            fields.push(field);
+        }
+
+        // the condition ID is the node ID of the condition itself
+        select_clauses.push(simple_attribute(
+            ObjectTypeId::ConditionType,
+            "",
+            AttributeId::NodeId,
+        ));
+        fields.push(CONDITION_ID.to_string());
+
+        Self {
+            fields,
+            filter: EventFilter {
+                select_clauses: Some(select_clauses),
+                where_clause: where_clause(types, events.min_severity),
+            },
+        }
+    }
+}
+
+/// Convert the fields of an event into a JSON object.
+pub fn to_json(fields: &[String], event: &EventFieldList, data_types: &DataTypes) -> Value {
+    let mut m = Map::new();
+    let mut timestamp = None;
+
+    let values = event.event_fields.iter().flatten();
+    for (name, value) in fields.iter().zip(values) {
+        if let ("Time", Variant::DateTime(time)) = (name.as_str(), value) {
+            timestamp = Some(time.as_chrono().to_json());
+        }
+        m.insert(name.clone(), data_types.variant_to_json(value.clone()));
+    }
+
+    let timestamp = timestamp.unwrap_or_else(|| super::now().into());
+    m.insert("timestamp".to_string(), timestamp);
+
+    Value::Object(m)
+}
+
+/// Create a simple attribute operand, the browse path segments are separated by `/`.
+///
+/// Segments can have a namespace prefix, like `2:Name`, and default to namespace zero.
+fn simple_attribute<T>(
+    type_definition_id: T,
+    path: &str,
+    attribute_id: AttributeId,
+) -> SimpleAttributeOperand
+where
+    T: Into<NodeId>,
+{
+    let browse_path = path
+        .split('/')
+        .filter(|s| !s.is_empty())
+        .map(qualified_name)
+        .collect::<Vec<_>>();
+
+    SimpleAttributeOperand {
+        type_definition_id: type_definition_id.into(),
+        browse_path: if browse_path.is_empty() {
+            None
+        } else {
+            Some(browse_path)
+        },
+        attribute_id: attribute_id as u32,
+        index_range: UAString::null(),
+    }
+}
+
+/// Parse a qualified name of the form `ns:Name`, the namespace being optional.
+fn qualified_name(name: &str) -> QualifiedName {
+    match name.split_once(':') {
+        Some((ns, name)) => match ns.parse::<u16>() {
+            Ok(ns) => QualifiedName::new(ns, name),
+            Err(_) => QualifiedName::new(0, name),
+        },
+        None => QualifiedName::new(0, name),
+    }
+}
+
+/// A condition of the where clause.
+enum Condition {
+    Element(FilterOperator, Vec<ExtensionObject>),
+    And(Box<Condition>, Box<Condition>),
+    Or(Box<Condition>, Box<Condition>),
+}
+
+/// Create the where clause, matching any of the event types (if any), and the minimum severity.
+fn where_clause(types: &[NodeId], min_severity: Option<u16>) -> ContentFilter {
+    let types = types
+        .iter()
+        .map(|t| {
+            Condition::Element(
+                FilterOperator::OfType,
+                vec![literal(Variant::NodeId(Box::new(t.clone())))],
+            )
+        })
+        .reduce(|a, b| Condition::Or(Box::new(a), Box::new(b)));
+
+    let severity = min_severity.map(|min_severity| {
+        let severity =
+            simple_attribute(ObjectTypeId::BaseEventType, "Severity", AttributeId::Value);
+        Condition::Element(
+            FilterOperator::GreaterThanOrEqual,
+            vec![
+                ExtensionObject::from_encodable(
+                    ObjectId::SimpleAttributeOperand_Encoding_DefaultBinary,
+                    &severity,
+                ),
+                literal(Variant::UInt16(min_severity)),
+            ],
+        )
+    });
+
+    let condition = match (types, severity) {
+        (Some(types), Some(severity)) => Some(Condition::And(Box::new(types), Box::new(severity))),
+        (types, severity) => types.or(severity),
+    };
+
+    let mut elements = vec![];
+    if let Some(condition) = condition {
+        flatten(condition, &mut elements);
+    }
+
+    ContentFilter {
+        elements: if elements.is_empty() {
+            None
+        } else {
+            Some(elements)
+        },
+    }
+}
+
+/// Flatten a condition into a list of filter elements, returning the index of the condition.
+///
+/// The elements are added in pre-order, so that the first element is the root of the tree.
+fn flatten(condition: Condition, elements: &mut Vec<ContentFilterElement>) -> u32 {
+    let index = elements.len();
+    // add a placeholder, replaced once we know the indexes of the operands
+    elements.push(ContentFilterElement {
+        filter_operator: FilterOperator::And,
+        filter_operands: None,
+    });
+
+    let (filter_operator, filter_operands) = match condition {
+        Condition::Element(operator, operands) => (operator, operands),
+        Condition::And(a, b) => (
+            FilterOperator::And,
+            vec![
+                element(flatten(*a, elements)),
+                element(flatten(*b, elements)),
+            ],
+        ),
+        Condition::Or(a, b) => (
+            FilterOperator::Or,
+            vec![
+                element(flatten(*a, elements)),
+                element(flatten(*b, elements)),
+            ],
+        ),
+    };
+
+    elements[index] = ContentFilterElement {
+        filter_operator,
+        filter_operands: Some(filter_operands),
+    };
+
+    index as u32
+}
+
+fn literal(value: Variant) -> ExtensionObject {
+    ExtensionObject::from_encodable(
+        ObjectId::LiteralOperand_Encoding_DefaultBinary,
+        &LiteralOperand { value },
+    )
+}
+
+fn element(index: u32) -> ExtensionObject {
+    ExtensionObject::from_encodable(
+        ObjectId::ElementOperand_Encoding_DefaultBinary,
+        &ElementOperand { index },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_qualified_name() {
+        assert_eq!(
+            qualified_name("Severity"),
+            QualifiedName::new(0, "Severity")
+        );
+        assert_eq!(qualified_name("2:Speed"), QualifiedName::new(2, "Speed"));
+    }
+
+    #[test]
+    fn test_filter_type_definitions() {
+        let filter = Filter::new(
+            &Events {
+                source: "i=2253".to_string(),
+                fields: vec!["2:Temperature".to_string()],
+                types: vec![],
+                min_severity: None,
+                queue_size: 100,
+            },
+            &[],
+        );
+
+        let select_clauses = filter.filter.select_clauses.unwrap();
+        assert_eq!(select_clauses.len(), filter.fields.len());
+        for (field, clause) in filter.fields.iter().zip(&select_clauses) {
+            let type_id = if field == CONDITION_ID || CONDITION_FIELDS.contains(&field.as_str()) {
+                ObjectTypeId::ConditionType
+            } else {
+                ObjectTypeId::BaseEventType
+            };
+            assert_eq!(clause.type_definition_id, type_id.into(), "{field}");
+        }
+    }
+
+    #[test]
+    fn test_where_clause_empty() {
+        assert_eq!(where_clause(&[], None).elements, None);
+    }
+
+    #[test]
+    fn test_where_clause() {
+        let types = [NodeId::new(0, 2915), NodeId::new(0, 9341)];
+        let elements = where_clause(&types, Some(500)).elements.unwrap();
+
+        // And(Or(OfType, OfType), GreaterThanOrEqual)
+        let operators = elements
+            .iter()
+            .map(|e| e.filter_operator)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            operators,
+            vec![
+                FilterOperator::And,
+                FilterOperator::Or,
+                FilterOperator::OfType,
+                FilterOperator::OfType,
+                FilterOperator::GreaterThanOrEqual,
+            ]
+        );
+        assert_eq!(
+            elements[0].filter_operands,
+            Some(vec![element(1), element(4)])
+        );
+        assert_eq!(
+            elements[1].filter_operands,
+            Some(vec![element(2), element(3)])
+        );
+    }
+}
@@ -0,0 +1,147 @@
+//! Monitoring of the server status.
+
+use super::{now, opcua::client::prelude::*, EventSender, RwLock, ServerMonitoring};
+use crate::{middleware::Update, ToJson};
+use chrono::Utc;
+use serde_json::{json, Map, Value};
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc,
+};
+
+/// Read the server status cyclically, until terminated.
+///
+/// This blocks the current thread.
+pub fn monitor(
+    connection: &str,
+    session: Arc<RwLock<Session>>,
+    monitoring: &ServerMonitoring,
+    terminated: &AtomicBool,
+    mut tx: EventSender,
+) {
+    let nodes_to_read = [
+        VariableId::Server_ServerStatus,
+        VariableId::Server_ServiceLevel,
+    ]
+    .map(|node_id| ReadValueId::from(NodeId::from(node_id)));
+
+    loop {
+        if terminated.load(Ordering::SeqCst) || tx.0.is_closed() {
+            break;
+        }
+
+        let sent = Utc::now();
+        let values = {
+            #[cfg(feature = "opcua_0_11")]
+            let session = session.read();
+            #[cfg(not(feature = "opcua_0_11"))]
+            let session = session.read().unwrap();
+
+            if session.is_connected() {
+                Some(session.read(&nodes_to_read, TimestampsToReturn::Neither, 0.0))
+            } else {
+                None
+            }
+        };
+        let received = Utc::now();
+
+        match values {
+            Some(Ok(values)) => {
+                let value = to_json(values, sent, received);
+                tx.update_blocking([Update::new(
+                    ["opcua", connection, "server"],
+                    connection,
+                    value,
+                )]);
+            }
+            Some(Err(status)) => log::info!("Failed to read the server status: {status}"),
+            None => {}
+        }
+
+        std::thread::sleep(monitoring.interval);
+    }
+
+    log::debug!("Stopped monitoring the server of {connection}");
+}
+
+/// Convert the status and service level into a JSON object.
+fn to_json(
+    values: Vec<DataValue>,
+    sent: chrono::DateTime<Utc>,
+    received: chrono::DateTime<Utc>,
+) -> Value {
+    let mut m = Map::new();
+    m.insert("timestamp".to_string(), now().into());
+
+    let mut values = values.into_iter().map(|value| value.value);
+
+    if let Some(Some(Variant::ExtensionObject(status))) = values.next() {
+        match status.decode_inner::<ServerStatusDataType>(&DecodingOptions::default()) {
+            Ok(status) => {
+                let current_time = status.current_time.as_chrono();
+                let offset = clock_offset(current_time, sent, received);
+
+                m.insert("state".to_string(), format!("{:?}", status.state).into());
+                m.insert("startTime".to_string(), status.start_time.to_json());
+                m.insert("currentTime".to_string(), status.current_time.to_json());
+                m.insert("clockOffset".to_string(), offset.num_milliseconds().into());
+                m.insert(
+                    "buildInfo".to_string(),
+                    json!({
+                        "productUri": status.build_info.product_uri.to_json(),
+                        "manufacturerName": status.build_info.manufacturer_name.to_json(),
+                        "productName": status.build_info.product_name.to_json(),
+                        "softwareVersion": status.build_info.software_version.to_json(),
+                        "buildNumber": status.build_info.build_number.to_json(),
+                        "buildDate": status.build_info.build_date.to_json(),
+                    }),
+                );
+            }
+            Err(status) => log::info!("Failed to decode the server status: {status}"),
+        }
+    }
+
+    if let Some(Some(Variant::Byte(level))) = values.next() {
+        m.insert("serviceLevel".to_string(), level.into());
+    }
+
+    Value::Object(m)
+}
+
+/// Calculate the offset of the server clock, relative to the agent's clock.
+///
+/// The server time is assumed to be taken halfway between sending the request and receiving
+/// the response.
+fn clock_offset(
+    server_time: chrono::DateTime<Utc>,
+    sent: chrono::DateTime<Utc>,
+    received: chrono::DateTime<Utc>,
+) -> chrono::Duration {
+    let agent_time = sent + (received - sent) / 2;
+    server_time - agent_time
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use chrono::TimeZone;
+
+    #[test]
+    fn test_clock_offset() {
+        let sent = Utc.timestamp(1_000, 0);
+        let received = Utc.timestamp(1_002, 0);
+
+        assert_eq!(
+            clock_offset(Utc.timestamp(1_001, 0), sent, received),
+            chrono::Duration::zero()
+        );
+        assert_eq!(
+            clock_offset(Utc.timestamp(1_011, 0), sent, received),
+            chrono::Duration::seconds(10)
+        );
+        assert_eq!(
+            clock_offset(Utc.timestamp(996, 0), sent, received),
+            chrono::Duration::seconds(-5)
+        );
+    }
+}
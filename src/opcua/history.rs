@@ -0,0 +1,529 @@
+//! Reading historical data, and backfilling values missed while being disconnected.
+
+use super::{address, opcua::client::prelude::*, structures::DataTypes, EventSender};
+use crate::middleware::Update;
+use chrono::Utc;
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+/// An aggregate function, supported for reading processed history.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Deserialize)]
+pub enum AggregateFunction {
+    Average,
+    Min,
+    Max,
+    Interpolated,
+}
+
+impl From<AggregateFunction> for NodeId {
+    fn from(value: AggregateFunction) -> Self {
+        match value {
+            AggregateFunction::Average => ObjectId::AggregateFunction_Average,
+            AggregateFunction::Min => ObjectId::AggregateFunction_Minimum,
+            AggregateFunction::Max => ObjectId::AggregateFunction_Maximum,
+            AggregateFunction::Interpolated => ObjectId::AggregateFunction_Interpolative,
+        }
+        .into()
+    }
+}
+
+/// An aggregate, processed over an interval.
+#[derive(Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Aggregate {
+    pub function: AggregateFunction,
+    #[serde(with = "humantime_serde")]
+    pub interval: Duration,
+}
+
+/// The details of a history read.
+#[derive(Clone, Debug)]
+pub enum Details {
+    Raw(ReadRawModifiedDetails),
+    Processed(ReadProcessedDetails),
+}
+
+impl Details {
+    /// Raw values, in the range of `start` to `end`.
+    pub fn raw(start: chrono::DateTime<Utc>, end: chrono::DateTime<Utc>) -> Self {
+        Self::Raw(ReadRawModifiedDetails {
+            is_read_modified: false,
+            start_time: start.into(),
+            end_time: end.into(),
+            num_values_per_node: 0,
+            return_bounds: false,
+        })
+    }
+
+    /// Raw values, returning at most `max_values` per node and request.
+    pub fn raw_paged(
+        start: chrono::DateTime<Utc>,
+        end: chrono::DateTime<Utc>,
+        max_values: u32,
+    ) -> Self {
+        let mut details = Self::raw(start, end);
+        if let Self::Raw(details) = &mut details {
+            details.num_values_per_node = max_values;
+        }
+        details
+    }
+
+    /// Processed values, in the range of `start` to `end`, for a number of nodes.
+    pub fn processed(
+        start: chrono::DateTime<Utc>,
+        end: chrono::DateTime<Utc>,
+        aggregate: &Aggregate,
+        nodes: usize,
+    ) -> Self {
+        Self::Processed(ReadProcessedDetails {
+            start_time: start.into(),
+            end_time: end.into(),
+            processing_interval: aggregate.interval.as_millis() as f64,
+            // one aggregate per node
+            aggregate_type: Some(vec![aggregate.function.into(); nodes]),
+            aggregate_configuration: AggregateConfiguration {
+                use_server_capabilities_defaults: true,
+                treat_uncertain_as_bad: false,
+                percent_data_bad: 100,
+                percent_data_good: 100,
+                use_sloped_extrapolation: false,
+            },
+        })
+    }
+
+    /// Processed values, split into pages of at most `max_values` intervals per node.
+    ///
+    /// The number of processed values can't be limited per request, so the range of `start` to
+    /// `end` is split instead.
+    pub fn processed_paged(
+        start: chrono::DateTime<Utc>,
+        end: chrono::DateTime<Utc>,
+        aggregate: &Aggregate,
+        nodes: usize,
+        max_values: u32,
+    ) -> Vec<Self> {
+        let page = aggregate
+            .interval
+            .checked_mul(max_values.max(1))
+            .and_then(|page| chrono::Duration::from_std(page).ok())
+            .filter(|page| *page > chrono::Duration::zero());
+        let page = match page {
+            Some(page) => page,
+            None => return vec![Self::processed(start, end, aggregate, nodes)],
+        };
+
+        let mut pages = vec![];
+        let mut page_start = start;
+        loop {
+            let page_end = page_start
+                .checked_add_signed(page)
+                .map_or(end, |page_end| page_end.min(end));
+            pages.push(Self::processed(page_start, page_end, aggregate, nodes));
+            if page_end >= end {
+                break;
+            }
+            page_start = page_end;
+        }
+        pages
+    }
+
+    /// The action for reading some of the nodes, by their index.
+    fn action(&self, indices: &[usize]) -> HistoryReadAction {
+        match self {
+            Self::Raw(details) => HistoryReadAction::ReadRawModifiedDetails(details.clone()),
+            Self::Processed(details) => {
+                let mut details = details.clone();
+                // one aggregate per node to read, which are fewer when continuing
+                details.aggregate_type = details.aggregate_type.map(|aggregate_type| {
+                    indices
+                        .iter()
+                        .filter_map(|index| aggregate_type.get(*index).cloned())
+                        .collect()
+                });
+                HistoryReadAction::ReadProcessedDetails(details)
+            }
+        }
+    }
+}
+
+/// Read the history of nodes, paging through continuation points.
+///
+/// The callback is invoked for every chunk of values returned by the server, along with the
+/// index of the node the values belong to. Returns the status of each node, as nodes may fail
+/// on their own. If the request itself fails, pending continuation points are released.
+pub fn read<F>(
+    session: &Session,
+    details: &Details,
+    node_ids: &[NodeId],
+    mut chunk: F,
+) -> Result<Vec<StatusCode>, StatusCode>
+where
+    F: FnMut(usize, Vec<DataValue>),
+{
+    let mut statuses = vec![StatusCode::Good; node_ids.len()];
+    let mut pending = (0..node_ids.len())
+        .map(|index| (index, ByteString::null()))
+        .collect::<Vec<_>>();
+
+    while !pending.is_empty() {
+        let indices = pending.iter().map(|(index, _)| *index).collect::<Vec<_>>();
+        let results = match session.history_read(
+            details.action(&indices),
+            TimestampsToReturn::Both,
+            false,
+            &history_read_value_ids(node_ids, &pending),
+        ) {
+            Ok(results) => results,
+            Err(status) => {
+                release(session, details, node_ids, &pending);
+                return Err(status);
+            }
+        };
+
+        let mut next = vec![];
+        let mut release_points = vec![];
+        for ((index, _), result) in pending.into_iter().zip(results) {
+            if !result.status_code.is_good() {
+                log::info!(
+                    "Failed to read history of {}: {}",
+                    node_ids[index],
+                    result.status_code
+                );
+                statuses[index] = result.status_code;
+                continue;
+            }
+
+            let data = match result
+                .history_data
+                .decode_inner::<HistoryData>(&DecodingOptions::default())
+            {
+                Ok(data) => data,
+                Err(status) => {
+                    log::info!("Failed to decode history of {}: {status}", node_ids[index]);
+                    statuses[index] = status;
+                    // no longer continued, so it must be released
+                    release_points.push((index, result.continuation_point));
+                    continue;
+                }
+            };
+            chunk(index, data.data_values.unwrap_or_default());
+
+            if !result.continuation_point.is_null() {
+                next.push((index, result.continuation_point));
+            }
+        }
+
+        release(session, details, node_ids, &release_points);
+        pending = next;
+    }
+
+    Ok(statuses)
+}
+
+fn history_read_value_ids(
+    node_ids: &[NodeId],
+    pending: &[(usize, ByteString)],
+) -> Vec<HistoryReadValueId> {
+    pending
+        .iter()
+        .map(|(index, continuation_point)| HistoryReadValueId {
+            node_id: node_ids[*index].clone(),
+            index_range: UAString::null(),
+            data_encoding: QualifiedName::null(),
+            continuation_point: continuation_point.clone(),
+        })
+        .collect()
+}
+
+/// Release continuation points, which won't be continued, so that the server can free them.
+fn release(
+    session: &Session,
+    details: &Details,
+    node_ids: &[NodeId],
+    pending: &[(usize, ByteString)],
+) {
+    let pending = pending
+        .iter()
+        .filter(|(_, continuation_point)| !continuation_point.is_null())
+        .cloned()
+        .collect::<Vec<_>>();
+    if pending.is_empty() {
+        return;
+    }
+
+    let indices = pending.iter().map(|(index, _)| *index).collect::<Vec<_>>();
+    if let Err(status) = session.history_read(
+        details.action(&indices),
+        TimestampsToReturn::Neither,
+        true,
+        &history_read_value_ids(node_ids, &pending),
+    ) {
+        log::info!("Failed to release continuation points: {status}");
+    }
+}
+
+/// A node seen by a subscription.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+struct Key {
+    subscription: String,
+    node_id: NodeId,
+}
+
+#[derive(Clone, Debug)]
+struct Seen {
+    name: String,
+    timestamp: chrono::DateTime<Utc>,
+}
+
+#[derive(Default)]
+struct State {
+    /// The most recent value timestamps
+    last_seen: HashMap<Key, Seen>,
+    /// The timestamps when the connection was lost, pending backfill
+    pending: HashMap<Key, Seen>,
+}
+
+/// Tracks the last seen values of subscriptions, and backfills the gaps after a reconnect.
+#[derive(Clone, Default)]
+pub struct Backfill {
+    state: Arc<Mutex<State>>,
+}
+
+impl Backfill {
+    /// Record the timestamp of a value received by a subscription.
+    pub fn seen(&self, subscription: &str, node_id: &NodeId, name: &str, value: &DataValue) {
+        let timestamp = match value.source_timestamp.or(value.server_timestamp) {
+            Some(timestamp) => timestamp.as_chrono(),
+            None => return,
+        };
+
+        let key = Key {
+            subscription: subscription.to_string(),
+            node_id: node_id.clone(),
+        };
+
+        let mut state = self.state.lock().unwrap();
+        match state.last_seen.get_mut(&key) {
+            Some(seen) if seen.timestamp < timestamp => seen.timestamp = timestamp,
+            Some(_) => {}
+            None => {
+                state.last_seen.insert(
+                    key,
+                    Seen {
+                        name: name.to_string(),
+                        timestamp,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Mark the connection as lost, remembering where to start the backfill.
+    ///
+    /// When called multiple times before a backfill, the earliest state is kept.
+    pub fn disconnected(&self) {
+        let mut state = self.state.lock().unwrap();
+        if state.pending.is_empty() {
+            state.pending = state.last_seen.clone();
+        }
+    }
+
+    /// Read and send the values missed since the connection was lost.
+    ///
+    /// If `since` is present, all seen nodes are backfilled from that point in time on.
+    /// Otherwise, nodes are backfilled from the last value seen before the connection was lost.
+    pub fn run(
+        &self,
+        connection: &str,
+        session: &Session,
+        since: Option<chrono::DateTime<Utc>>,
+        data_types: &DataTypes,
+        tx: &mut EventSender,
+    ) {
+        let nodes = {
+            let mut state = self.state.lock().unwrap();
+            match since {
+                Some(since) => state
+                    .last_seen
+                    .iter()
+                    .map(|(key, seen)| {
+                        let mut seen = seen.clone();
+                        seen.timestamp = since.min(seen.timestamp);
+                        (key.clone(), seen)
+                    })
+                    .collect::<Vec<_>>(),
+                None => std::mem::take(&mut state.pending).into_iter().collect(),
+            }
+        };
+
+        if nodes.is_empty() {
+            return;
+        }
+
+        log::info!("Backfilling {} nodes of {connection}", nodes.len());
+
+        let end = Utc::now();
+
+        for (key, seen) in nodes {
+            let details = Details::raw(seen.timestamp, end);
+            let address = address(connection, &key.subscription, &seen.name);
+
+            let mut latest = seen.timestamp;
+            let result = read(session, &details, &[key.node_id.clone()], |_, values| {
+                for value in values {
+                    let timestamp = value
+                        .source_timestamp
+                        .or(value.server_timestamp)
+                        .map(|timestamp| timestamp.as_chrono());
+
+                    // skip the value we already have
+                    if matches!(timestamp, Some(timestamp) if timestamp <= seen.timestamp) {
+                        continue;
+                    }
+                    if let Some(timestamp) = timestamp {
+                        latest = latest.max(timestamp);
+                    }
+
+                    let mut value = data_types.to_json(value);
+                    if let Value::Object(value) = &mut value {
+                        value.insert("historical".to_string(), true.into());
+                    }
+
+                    let mut update = Update::new(address.clone(), connection, value);
+                    update
+                        .extensions
+                        .insert("nodeId".to_string(), key.node_id.to_string().into());
+                    update
+                        .extensions
+                        .insert("historical".to_string(), true.into());
+
+                    // send one by one, in order, so that the values don't get compacted
+                    tx.update_blocking([update]);
+                }
+            });
+
+            let status = match result {
+                Ok(statuses) => statuses.into_iter().next().unwrap_or(StatusCode::Good),
+                Err(status) => status,
+            };
+            if !status.is_good() {
+                log::info!("Failed to backfill {}: {status}", key.node_id);
+                continue;
+            }
+
+            let mut state = self.state.lock().unwrap();
+            if let Some(seen) = state.last_seen.get_mut(&key) {
+                seen.timestamp = seen.timestamp.max(latest);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_aggregate() {
+        let aggregate: Aggregate = serde_json::from_value(json!({
+            "function": "Average",
+            "interval": "5m",
+        }))
+        .unwrap();
+
+        assert_eq!(
+            aggregate,
+            Aggregate {
+                function: AggregateFunction::Average,
+                interval: Duration::from_secs(300),
+            }
+        );
+    }
+
+    #[test]
+    fn test_processed() {
+        let aggregate = Aggregate {
+            function: AggregateFunction::Max,
+            interval: Duration::from_secs(60),
+        };
+        let now = Utc::now();
+
+        match Details::processed(now, now, &aggregate, 2) {
+            Details::Processed(details) => {
+                assert_eq!(details.processing_interval, 60_000.0);
+                assert_eq!(
+                    details.aggregate_type,
+                    Some(vec![
+                        ObjectId::AggregateFunction_Maximum.into(),
+                        ObjectId::AggregateFunction_Maximum.into()
+                    ])
+                );
+            }
+            details => panic!("Unexpected details: {details:?}"),
+        }
+    }
+
+    #[test]
+    fn test_processed_paged() {
+        let aggregate = Aggregate {
+            function: AggregateFunction::Average,
+            interval: Duration::from_secs(60),
+        };
+        let start = chrono::DateTime::parse_from_rfc3339("2022-01-02T03:04:05Z")
+            .unwrap()
+            .with_timezone(&Utc);
+        let end = start + chrono::Duration::minutes(10);
+
+        let ranges = Details::processed_paged(start, end, &aggregate, 1, 3)
+            .into_iter()
+            .map(|details| match details {
+                Details::Processed(details) => (
+                    details.start_time.as_chrono() - start,
+                    details.end_time.as_chrono() - start,
+                ),
+                details => panic!("Unexpected details: {details:?}"),
+            })
+            .collect::<Vec<_>>();
+
+        let minutes = chrono::Duration::minutes;
+        assert_eq!(
+            ranges,
+            vec![
+                (minutes(0), minutes(3)),
+                (minutes(3), minutes(6)),
+                (minutes(6), minutes(9)),
+                (minutes(9), minutes(10)),
+            ]
+        );
+
+        assert_eq!(
+            Details::processed_paged(start, start, &aggregate, 1, 3).len(),
+            1
+        );
+    }
+
+    #[test]
+    fn test_processed_action() {
+        let aggregate = Aggregate {
+            function: AggregateFunction::Min,
+            interval: Duration::from_secs(60),
+        };
+        let now = Utc::now();
+
+        match Details::processed(now, now, &aggregate, 3).action(&[2]) {
+            HistoryReadAction::ReadProcessedDetails(details) => {
+                assert_eq!(
+                    details.aggregate_type,
+                    Some(vec![ObjectId::AggregateFunction_Minimum.into()])
+                );
+            }
+            _ => panic!("Unexpected action"),
+        }
+    }
+}
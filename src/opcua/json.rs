@@ -0,0 +1,474 @@
+//! The OPC UA JSON encoding, as defined by part 6 of the specification (version 1.04).
+//!
+//! The reversible form keeps all type information, so that values can be decoded again. The
+//! non-reversible form is meant for consumers which don't care about OPC UA types, e.g. variants
+//! are encoded as their plain value, and namespaces by their URI.
+
+use super::{opcua::client::prelude::*, structures::DataTypes};
+use crate::ToJson;
+use chrono::SecondsFormat;
+use serde_json::{json, Map, Value};
+
+/// Encodes values to JSON, in the reversible or the non-reversible form.
+pub struct Encoder<'a> {
+    /// Use the reversible form
+    pub reversible: bool,
+    /// The namespace array of the server, for encoding namespace URIs
+    pub namespaces: &'a [String],
+    /// Decoding of structures, for the non-reversible form
+    pub data_types: &'a DataTypes,
+}
+
+impl Encoder<'_> {
+    pub fn data_value(&self, value: &DataValue) -> Value {
+        let mut m = Map::new();
+
+        if let Some(value) = value
+            .value
+            .as_ref()
+            .filter(|value| !matches!(value, Variant::Empty))
+        {
+            m.insert("Value".to_string(), self.variant(value));
+        }
+        if let Some(status) = value.status.filter(|status| status.bits() != 0) {
+            m.insert("Status".to_string(), self.status_code(status));
+        }
+        if let Some(timestamp) = &value.source_timestamp {
+            m.insert("SourceTimestamp".to_string(), date_time(timestamp));
+        }
+        if let Some(picoseconds) = value.source_picoseconds.filter(|ps| *ps != 0) {
+            m.insert("SourcePicoseconds".to_string(), picoseconds.into());
+        }
+        if let Some(timestamp) = &value.server_timestamp {
+            m.insert("ServerTimestamp".to_string(), date_time(timestamp));
+        }
+        if let Some(picoseconds) = value.server_picoseconds.filter(|ps| *ps != 0) {
+            m.insert("ServerPicoseconds".to_string(), picoseconds.into());
+        }
+
+        Value::Object(m)
+    }
+
+    /// Encode a variant, with its type in the reversible form, or as plain value otherwise.
+    pub fn variant(&self, value: &Variant) -> Value {
+        match value {
+            Variant::Empty => Value::Null,
+            Variant::Array(array) => {
+                let values = array
+                    .values
+                    .iter()
+                    .map(|value| match array.value_type {
+                        VariantTypeId::Variant => self.variant(value),
+                        _ => self.body(value),
+                    })
+                    .collect::<Vec<_>>();
+
+                if !self.reversible {
+                    return nest(values, &array.dimensions);
+                }
+
+                let mut m = Map::new();
+                m.insert("Type".to_string(), type_id(array.value_type).into());
+                m.insert("Body".to_string(), Value::Array(values));
+                if array.dimensions.len() > 1 {
+                    m.insert("Dimensions".to_string(), json!(array.dimensions));
+                }
+                Value::Object(m)
+            }
+            value if self.reversible => json!({
+                "Type": type_id(value.type_id()),
+                "Body": self.body(value),
+            }),
+            value => self.body(value),
+        }
+    }
+
+    pub fn node_id(&self, node_id: &NodeId) -> Value {
+        let mut m = Map::new();
+
+        let (id_type, id) = match &node_id.identifier {
+            Identifier::Numeric(id) => (0, Value::from(*id)),
+            Identifier::String(id) => (1, string(id)),
+            Identifier::Guid(id) => (2, id.to_string().into()),
+            Identifier::ByteString(id) => (3, id.value.clone().to_json()),
+        };
+        if id_type != 0 {
+            m.insert("IdType".to_string(), id_type.into());
+        }
+        m.insert("Id".to_string(), id);
+        if node_id.namespace != 0 {
+            m.insert("Namespace".to_string(), self.namespace(node_id.namespace));
+        }
+
+        Value::Object(m)
+    }
+
+    /// Encode a status code, as number in the reversible form, or with its symbol otherwise.
+    pub fn status_code(&self, status: StatusCode) -> Value {
+        if self.reversible {
+            status.bits().into()
+        } else {
+            json!({
+                "Code": status.bits(),
+                "Symbol": status.name(),
+            })
+        }
+    }
+
+    /// Encode the value of a variant, without its type.
+    fn body(&self, value: &Variant) -> Value {
+        match value {
+            Variant::Empty => Value::Null,
+            Variant::Boolean(value) => (*value).into(),
+            Variant::SByte(value) => (*value).into(),
+            Variant::Byte(value) => (*value).into(),
+            Variant::Int16(value) => (*value).into(),
+            Variant::UInt16(value) => (*value).into(),
+            Variant::Int32(value) => (*value).into(),
+            Variant::UInt32(value) => (*value).into(),
+            // 64 bit integers are encoded as strings, as JSON parsers commonly use doubles
+            Variant::Int64(value) => value.to_string().into(),
+            Variant::UInt64(value) => value.to_string().into(),
+            Variant::Float(value) => float(*value as f64),
+            Variant::Double(value) => float(*value),
+            Variant::String(value) => string(value),
+            Variant::DateTime(value) => date_time(value),
+            Variant::Guid(value) => value.to_string().into(),
+            Variant::StatusCode(value) => self.status_code(*value),
+            Variant::ByteString(value) => value.value.clone().to_json(),
+            Variant::XmlElement(value) => string(value),
+            Variant::QualifiedName(value) => self.qualified_name(value),
+            Variant::LocalizedText(value) => self.localized_text(value),
+            Variant::NodeId(value) => self.node_id(value),
+            Variant::ExpandedNodeId(value) => self.expanded_node_id(value),
+            Variant::ExtensionObject(value) => self.extension_object(value),
+            Variant::Variant(value) => self.variant(value),
+            Variant::DataValue(value) => self.data_value(value),
+            Variant::Diagnostics(value) => self.diagnostic_info(value),
+            Variant::Array(_) => self.variant(value),
+        }
+    }
+
+    /// Encode a namespace index, or its URI in the non-reversible form.
+    ///
+    /// The index is kept for namespace 1, the local server, and for unknown namespaces.
+    fn namespace(&self, index: u16) -> Value {
+        match self.namespaces.get(index as usize) {
+            Some(uri) if !self.reversible && index > 1 => uri.clone().into(),
+            _ => index.into(),
+        }
+    }
+
+    fn expanded_node_id(&self, value: &ExpandedNodeId) -> Value {
+        let mut json = self.node_id(&value.node_id);
+        if let Value::Object(m) = &mut json {
+            if !value.namespace_uri.is_null() {
+                m.insert("Namespace".to_string(), string(&value.namespace_uri));
+            }
+            if value.server_index != 0 {
+                m.insert("ServerUri".to_string(), value.server_index.into());
+            }
+        }
+        json
+    }
+
+    fn qualified_name(&self, value: &QualifiedName) -> Value {
+        let mut m = Map::new();
+        m.insert("Name".to_string(), string(&value.name));
+        if value.namespace_index != 0 {
+            m.insert("Uri".to_string(), self.namespace(value.namespace_index));
+        }
+        Value::Object(m)
+    }
+
+    fn localized_text(&self, value: &LocalizedText) -> Value {
+        if !self.reversible {
+            return string(&value.text);
+        }
+
+        let mut m = Map::new();
+        if !value.locale.is_empty() {
+            m.insert("Locale".to_string(), string(&value.locale));
+        }
+        m.insert("Text".to_string(), string(&value.text));
+        Value::Object(m)
+    }
+
+    /// Encode an extension object.
+    ///
+    /// Known structures are decoded in the non-reversible form. Otherwise, the encoded body is
+    /// kept, as the decoded fields would lose their types.
+    fn extension_object(&self, value: &ExtensionObject) -> Value {
+        if !self.reversible {
+            if let Some(decoded) = self.data_types.decode_object(value) {
+                return decoded;
+            }
+        }
+
+        let (encoding, body) = match &value.body {
+            ExtensionObjectEncoding::None => (None, Value::Null),
+            ExtensionObjectEncoding::ByteString(body) => (Some(1), body.value.clone().to_json()),
+            ExtensionObjectEncoding::XmlElement(body) => (Some(2), string(body)),
+        };
+
+        let mut m = Map::new();
+        m.insert("TypeId".to_string(), self.node_id(&value.node_id));
+        if let Some(encoding) = encoding {
+            m.insert("Encoding".to_string(), encoding.into());
+        }
+        m.insert("Body".to_string(), body);
+        Value::Object(m)
+    }
+
+    fn diagnostic_info(&self, value: &DiagnosticInfo) -> Value {
+        let mut m = Map::new();
+        if let Some(symbolic_id) = value.symbolic_id {
+            m.insert("SymbolicId".to_string(), symbolic_id.into());
+        }
+        if let Some(namespace_uri) = value.namespace_uri {
+            m.insert("NamespaceUri".to_string(), namespace_uri.into());
+        }
+        if let Some(locale) = value.locale {
+            m.insert("Locale".to_string(), locale.into());
+        }
+        if let Some(localized_text) = value.localized_text {
+            m.insert("LocalizedText".to_string(), localized_text.into());
+        }
+        if let Some(additional_info) = &value.additional_info {
+            m.insert("AdditionalInfo".to_string(), string(additional_info));
+        }
+        if let Some(status) = value.inner_status_code {
+            m.insert("InnerStatusCode".to_string(), self.status_code(status));
+        }
+        if let Some(inner) = &value.inner_diagnostic_info {
+            m.insert(
+                "InnerDiagnosticInfo".to_string(),
+                self.diagnostic_info(inner),
+            );
+        }
+        Value::Object(m)
+    }
+}
+
+/// Get the built-in type ID of a variant type.
+fn type_id(variant_type: VariantTypeId) -> u32 {
+    match variant_type {
+        VariantTypeId::Empty => 0,
+        VariantTypeId::Boolean => 1,
+        VariantTypeId::SByte => 2,
+        VariantTypeId::Byte => 3,
+        VariantTypeId::Int16 => 4,
+        VariantTypeId::UInt16 => 5,
+        VariantTypeId::Int32 => 6,
+        VariantTypeId::UInt32 => 7,
+        VariantTypeId::Int64 => 8,
+        VariantTypeId::UInt64 => 9,
+        VariantTypeId::Float => 10,
+        VariantTypeId::Double => 11,
+        VariantTypeId::String => 12,
+        VariantTypeId::DateTime => 13,
+        VariantTypeId::Guid => 14,
+        VariantTypeId::ByteString => 15,
+        VariantTypeId::XmlElement => 16,
+        VariantTypeId::NodeId => 17,
+        VariantTypeId::ExpandedNodeId => 18,
+        VariantTypeId::StatusCode => 19,
+        VariantTypeId::QualifiedName => 20,
+        VariantTypeId::LocalizedText => 21,
+        VariantTypeId::ExtensionObject => 22,
+        VariantTypeId::DataValue => 23,
+        VariantTypeId::Variant => 24,
+        VariantTypeId::Diagnostics => 25,
+        // arrays are encoded with the type of their values
+        VariantTypeId::Array => 0,
+    }
+}
+
+/// Nest the flattened values of a multi-dimensional array.
+///
+/// The values are kept flat, if they don't match the dimensions.
+fn nest(values: Vec<Value>, dimensions: &[u32]) -> Value {
+    let (first, rest) = match dimensions {
+        [first, rest @ ..] if !rest.is_empty() => (*first as usize, rest),
+        _ => return Value::Array(values),
+    };
+
+    let len = rest
+        .iter()
+        .map(|dimension| *dimension as usize)
+        .product::<usize>();
+    if first * len != values.len() {
+        return Value::Array(values);
+    }
+
+    let mut values = values.into_iter();
+    (0..first)
+        .map(|_| nest(values.by_ref().take(len).collect(), rest))
+        .collect()
+}
+
+fn string(value: &UAString) -> Value {
+    if value.is_null() {
+        Value::Null
+    } else {
+        value.as_ref().into()
+    }
+}
+
+/// Encode a float, using strings for the special values, which JSON numbers don't support.
+fn float(value: f64) -> Value {
+    if value.is_nan() {
+        "NaN".into()
+    } else if value == f64::INFINITY {
+        "Infinity".into()
+    } else if value == f64::NEG_INFINITY {
+        "-Infinity".into()
+    } else {
+        value.into()
+    }
+}
+
+/// Encode a timestamp, keeping the full precision.
+fn date_time(value: &DateTime) -> Value {
+    value
+        .as_chrono()
+        .to_rfc3339_opts(SecondsFormat::AutoSi, true)
+        .into()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn encoder<'a>(
+        reversible: bool,
+        namespaces: &'a [String],
+        data_types: &'a DataTypes,
+    ) -> Encoder<'a> {
+        Encoder {
+            reversible,
+            namespaces,
+            data_types,
+        }
+    }
+
+    #[test]
+    fn test_variant() {
+        let data_types = DataTypes::default();
+        let reversible = encoder(true, &[], &data_types);
+        let non_reversible = encoder(false, &[], &data_types);
+
+        assert_eq!(
+            reversible.variant(&Variant::Int16(-5)),
+            json!({"Type": 4, "Body": -5})
+        );
+        assert_eq!(non_reversible.variant(&Variant::Int16(-5)), json!(-5));
+        assert_eq!(
+            reversible.variant(&Variant::UInt64(u64::MAX)),
+            json!({"Type": 9, "Body": "18446744073709551615"})
+        );
+        assert_eq!(
+            non_reversible.variant(&Variant::Double(f64::NAN)),
+            json!("NaN")
+        );
+        assert_eq!(
+            non_reversible.variant(&Variant::Float(f32::NEG_INFINITY)),
+            json!("-Infinity")
+        );
+        assert_eq!(reversible.variant(&Variant::Empty), Value::Null);
+        assert_eq!(
+            reversible.variant(&Variant::from(LocalizedText::new("en", "Speed"))),
+            json!({"Type": 21, "Body": {"Locale": "en", "Text": "Speed"}})
+        );
+        assert_eq!(
+            non_reversible.variant(&Variant::from(LocalizedText::new("en", "Speed"))),
+            json!("Speed")
+        );
+    }
+
+    #[test]
+    fn test_array() {
+        let data_types = DataTypes::default();
+        let array = Variant::Array(Box::new(
+            Array::new_multi(
+                VariantTypeId::Int32,
+                (1..=6).map(Variant::Int32).collect::<Vec<_>>(),
+                vec![2, 3],
+            )
+            .unwrap(),
+        ));
+
+        assert_eq!(
+            encoder(true, &[], &data_types).variant(&array),
+            json!({"Type": 6, "Body": [1, 2, 3, 4, 5, 6], "Dimensions": [2, 3]})
+        );
+        assert_eq!(
+            encoder(false, &[], &data_types).variant(&array),
+            json!([[1, 2, 3], [4, 5, 6]])
+        );
+        assert_eq!(nest(vec![json!(1), json!(2)], &[2, 3]), json!([1, 2]));
+    }
+
+    #[test]
+    fn test_node_id() {
+        let data_types = DataTypes::default();
+        let namespaces = [
+            "http://opcfoundation.org/UA/".to_string(),
+            "urn:server".to_string(),
+            "urn:machine".to_string(),
+        ];
+        let reversible = encoder(true, &namespaces, &data_types);
+        let non_reversible = encoder(false, &namespaces, &data_types);
+
+        assert_eq!(
+            reversible.node_id(&NodeId::new(0, 2253u32)),
+            json!({"Id": 2253})
+        );
+        assert_eq!(
+            reversible.node_id(&NodeId::new(2, "Pump3")),
+            json!({"IdType": 1, "Id": "Pump3", "Namespace": 2})
+        );
+        assert_eq!(
+            non_reversible.node_id(&NodeId::new(2, "Pump3")),
+            json!({"IdType": 1, "Id": "Pump3", "Namespace": "urn:machine"})
+        );
+        assert_eq!(
+            non_reversible.node_id(&NodeId::new(1, "Pump3")),
+            json!({"IdType": 1, "Id": "Pump3", "Namespace": 1})
+        );
+        assert_eq!(
+            non_reversible.node_id(&NodeId::new(5, "Pump3")),
+            json!({"IdType": 1, "Id": "Pump3", "Namespace": 5})
+        );
+    }
+
+    #[test]
+    fn test_data_value() {
+        let data_types = DataTypes::default();
+        let timestamp = chrono::DateTime::parse_from_rfc3339("2022-01-02T03:04:05.1234567Z")
+            .unwrap()
+            .with_timezone(&chrono::Utc);
+        let value = DataValue {
+            value: Some(Variant::Boolean(true)),
+            status: Some(StatusCode::UncertainLastUsableValue),
+            source_timestamp: Some(DateTime::from(timestamp)),
+            source_picoseconds: Some(500),
+            server_timestamp: None,
+            server_picoseconds: None,
+        };
+
+        assert_eq!(
+            encoder(true, &[], &data_types).data_value(&value),
+            json!({
+                "Value": {"Type": 1, "Body": true},
+                "Status": StatusCode::UncertainLastUsableValue.bits(),
+                "SourceTimestamp": "2022-01-02T03:04:05.123456700Z",
+                "SourcePicoseconds": 500,
+            })
+        );
+        assert_eq!(
+            encoder(false, &[], &data_types).data_value(&DataValue::value_only(1.5f64)),
+            json!({"Value": 1.5})
+        );
+    }
+}
@@ -0,0 +1,146 @@
+//! Reading of node metadata, like display names and engineering units.
+
+use super::{opcua::client::prelude::*, services};
+use crate::ToJson;
+use serde_json::{json, Map, Value};
+
+/// Read the metadata of a node, as JSON object.
+///
+/// This reads the display name and description attributes, as well as the `EngineeringUnits`
+/// and `EURange` properties. Missing ones are omitted. Failures are logged, returning `None`.
+pub fn read(session: &Session, node_id: &NodeId) -> Option<Value> {
+    match try_read(session, node_id) {
+        Ok(metadata) => Some(metadata),
+        Err(status) => {
+            log::info!("Failed to read metadata of {node_id}: {status}");
+            None
+        }
+    }
+}
+
+fn try_read(session: &Session, node_id: &NodeId) -> Result<Value, StatusCode> {
+    let nodes_to_read =
+        [AttributeId::DisplayName, AttributeId::Description].map(|attribute_id| ReadValueId {
+            node_id: node_id.clone(),
+            attribute_id: attribute_id as u32,
+            index_range: UAString::null(),
+            data_encoding: QualifiedName::null(),
+        });
+
+    let mut values = session
+        .read(&nodes_to_read, TimestampsToReturn::Neither, 0.0)?
+        .into_iter()
+        .map(|value| match value.value {
+            Some(Variant::LocalizedText(text)) => Some(*text),
+            _ => None,
+        });
+    let display_name = values.next().flatten();
+    let description = values.next().flatten();
+
+    let engineering_units = read_engineering_units(session, node_id)?;
+    let eu_range = services::read_eu_range(session, node_id)?;
+
+    Ok(to_json(
+        display_name,
+        description,
+        engineering_units,
+        eu_range,
+    ))
+}
+
+/// Read the EngineeringUnits property of a node, if it has one.
+fn read_engineering_units(
+    session: &Session,
+    node_id: &NodeId,
+) -> Result<Option<EUInformation>, StatusCode> {
+    let property =
+        match services::find_property(session, node_id, QualifiedName::new(0, "EngineeringUnits"))?
+        {
+            Some(property) => property,
+            None => return Ok(None),
+        };
+
+    match services::read_value(session, &property)? {
+        Some(Variant::ExtensionObject(value)) => value
+            .decode_inner::<EUInformation>(&DecodingOptions::default())
+            .map(Some),
+        _ => Ok(None),
+    }
+}
+
+fn to_json(
+    display_name: Option<LocalizedText>,
+    description: Option<LocalizedText>,
+    engineering_units: Option<EUInformation>,
+    eu_range: Option<Range>,
+) -> Value {
+    let mut m = Map::new();
+
+    if let Some(display_name) = display_name.filter(|text| !text.text.is_empty()) {
+        m.insert("displayName".to_string(), display_name.text.to_json());
+    }
+    if let Some(description) = description.filter(|text| !text.text.is_empty()) {
+        m.insert("description".to_string(), description.text.to_json());
+    }
+    if let Some(units) = engineering_units {
+        m.insert(
+            "engineeringUnits".to_string(),
+            json!({
+                "displayName": units.display_name.text.to_json(),
+                "description": units.description.text.to_json(),
+                "unitId": units.unit_id,
+                "namespaceUri": units.namespace_uri.to_json(),
+            }),
+        );
+    }
+    if let Some(range) = eu_range {
+        m.insert(
+            "euRange".to_string(),
+            json!({
+                "low": range.low,
+                "high": range.high,
+            }),
+        );
+    }
+
+    Value::Object(m)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_to_json() {
+        assert_eq!(to_json(None, None, None, None), json!({}));
+        assert_eq!(
+            to_json(
+                Some(LocalizedText::new("", "Speed")),
+                Some(LocalizedText::null()),
+                Some(EUInformation {
+                    namespace_uri: "http://www.opcfoundation.org/UA/units/un/cefact".into(),
+                    unit_id: 4534135,
+                    display_name: LocalizedText::new("", "rpm"),
+                    description: LocalizedText::new("", "revolutions per minute"),
+                }),
+                Some(Range {
+                    low: 0.0,
+                    high: 3000.0,
+                }),
+            ),
+            json!({
+                "displayName": "Speed",
+                "engineeringUnits": {
+                    "displayName": "rpm",
+                    "description": "revolutions per minute",
+                    "unitId": 4534135,
+                    "namespaceUri": "http://www.opcfoundation.org/UA/units/un/cefact",
+                },
+                "euRange": {
+                    "low": 0.0,
+                    "high": 3000.0,
+                },
+            })
+        );
+    }
+}
@@ -1,4 +1,20 @@
+mod browse;
+mod coerce;
+mod commands;
 mod config;
+mod dictionary;
+mod endpoints;
+mod events;
+mod health;
+mod history;
+mod json;
+mod metadata;
+mod pki;
+mod poll;
+mod resolve;
+mod retry;
+mod services;
+mod structures;
 
 pub use config::*;
 
@@ -19,18 +35,22 @@ use crate::{
     ToJson,
 };
 use anyhow::{anyhow, bail};
+use commands::CommandHandler;
 use futures::{
-    channel::mpsc::{channel, Receiver, Sender},
+    channel::mpsc::{channel, unbounded, Receiver, Sender, UnboundedReceiver, UnboundedSender},
     Sink, SinkExt, Stream, StreamExt,
 };
 use opcua::client::prelude::*;
 use serde_json::{json, Value};
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     ops::{Deref, DerefMut},
     path::PathBuf,
     str::FromStr,
-    sync::Arc,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
     time::{Duration, SystemTime},
 };
 use tokio::{runtime::Handle, spawn, sync::oneshot, task::spawn_blocking};
@@ -39,9 +59,32 @@ pub struct OpcUaConnector {
     connections: HashMap<String, OpcUaConnection>,
 }
 
+#[derive(Clone)]
 pub struct OpcUaConnection {
     id: String,
     config: Connection,
+    /// Node IDs discovered by browsing, by subscription
+    discovered: Arc<Mutex<HashMap<String, HashSet<String>>>>,
+    /// Names of the nodes, by subscription, reported as unsubscribed when the session closes
+    node_names: Arc<Mutex<HashMap<String, Vec<String>>>>,
+    /// Subscriptions created with the current session, by ID
+    subscriptions: Arc<Mutex<HashMap<String, Subscribed>>>,
+    /// Last seen values, for backfilling subscriptions
+    backfill: history::Backfill,
+    /// Resolves namespace URIs and browse paths of node IDs
+    node_ids: resolve::NodeIds,
+    /// Nodes of subscriptions in poll mode
+    poller: poll::Poller,
+    /// Data types of the server, for decoding and encoding structures
+    data_types: structures::DataTypes,
+    /// Failed monitored items, for retrying
+    retry: retry::Retry,
+    /// The currently running session
+    session: Arc<Mutex<Option<Arc<RwLock<Session>>>>>,
+    /// Stopping the currently running session
+    stop: Arc<Mutex<Option<oneshot::Sender<SessionCommand>>>>,
+    /// Set when the connection should no longer be restarted
+    stopped: Arc<AtomicBool>,
 }
 
 pub struct EventStream(Receiver<Event>);
@@ -74,13 +117,72 @@ pub struct SubscriptionEventSender {
     connection: String,
     subscription: String,
     sender: EventSender,
+    /// Node names, by node ID
+    nodes: Arc<Mutex<HashMap<NodeId, String>>>,
+    /// Node metadata, by node ID, if enabled
+    metadata: Arc<Mutex<HashMap<NodeId, Value>>>,
+    /// Data types of the server, for decoding structures
+    data_types: structures::DataTypes,
+    /// Names of the selected event fields
+    event_fields: Arc<Vec<String>>,
+    /// Tracking of seen values, if the subscription is backfilled
+    backfill: Option<history::Backfill>,
 }
 
 #[derive(Clone)]
 pub struct ConnectionEventSender {
     connection: String,
+    /// The URL of the connected endpoint
+    endpoint: String,
     sender: EventSender,
-    subscriptions: HashMap<String, Vec<String>>,
+    /// Names of the nodes, by subscription
+    subscriptions: Arc<Mutex<HashMap<String, Vec<String>>>>,
+    /// Notified when the connection was re-established
+    reconnected: UnboundedSender<()>,
+    backfill: history::Backfill,
+}
+
+/// A subscription created on the server.
+#[derive(Clone)]
+struct Subscribed {
+    subscription_id: u32,
+    /// Node names, by node ID, shared with the notification callback
+    nodes: Arc<Mutex<HashMap<NodeId, String>>>,
+    /// Node metadata, by node ID, shared with the notification callback
+    metadata: Arc<Mutex<HashMap<NodeId, Value>>>,
+    /// Monitored item IDs, by node name
+    monitored_items: Arc<Mutex<HashMap<String, u32>>>,
+}
+
+impl Subscribed {
+    /// Register a node, mapping its notifications to its name, and metadata if enabled.
+    fn add_node(&self, session: &Session, node_id: &NodeId, name: &str, with_metadata: bool) {
+        self.nodes
+            .lock()
+            .unwrap()
+            .insert(node_id.clone(), name.to_string());
+
+        if with_metadata {
+            if let Some(value) = metadata::read(session, node_id) {
+                self.metadata.lock().unwrap().insert(node_id.clone(), value);
+            }
+        }
+    }
+
+    /// Remove a node, by name, returning its monitored item ID, if it has one.
+    fn remove_node(&self, name: &str) -> Option<u32> {
+        let mut nodes = self.nodes.lock().unwrap();
+        let mut metadata = self.metadata.lock().unwrap();
+        nodes.retain(|node_id, candidate| {
+            let keep = candidate != name;
+            if !keep {
+                metadata.remove(node_id);
+            }
+            keep
+        });
+
+        self.monitored_items.lock().unwrap().remove(name)
+    }
 }
 
 impl EventSender {
@@ -98,51 +200,146 @@ impl EventSender {
             }
         });
     }
+
+    /// Send batches of updates, each as its own event, keeping their order.
+    ///
+    /// Updates of the same event get compacted, so this is required when sending multiple
+    /// updates for the same address.
+    fn events_sync<I>(&mut self, batches: I)
+    where
+        I: IntoIterator<Item = Vec<Update>>,
+    {
+        let mut tx = self.clone();
+        let events = batches
+            .into_iter()
+            .filter(|updates| !updates.is_empty())
+            .map(|updates| Event { updates })
+            .collect::<Vec<_>>();
+        Handle::current().spawn(async move {
+            for event in events {
+                if let Err(err) = tx.0.send(event).await {
+                    log::warn!("Failed to queue updates: {err}");
+                }
+            }
+        });
+    }
+
+    /// Send updates, blocking until they are queued.
+    ///
+    /// Must not be called from within an async context.
+    fn update_blocking<I>(&mut self, updates: I)
+    where
+        I: IntoIterator<Item = Update>,
+    {
+        let event = Event {
+            updates: updates.into_iter().collect(),
+        };
+        if let Err(err) = futures::executor::block_on(self.0.send(event)) {
+            log::warn!("Failed to queue updates: {err}");
+        }
+    }
+}
+
+impl SubscriptionEventSender {
+    /// Get the name of a node, falling back to the node ID if the node is unknown.
+    fn name(&self, node_id: &NodeId) -> String {
+        self.nodes
+            .lock()
+            .unwrap()
+            .get(node_id)
+            .cloned()
+            .unwrap_or_else(|| node_id.to_string())
+    }
 }
 
 impl OnSubscriptionNotification for SubscriptionEventSender {
     fn on_data_change(&mut self, data_change_items: &[&MonitoredItem]) {
-        let mut updates = Vec::with_capacity(data_change_items.len());
+        // the n-th queued value of each item goes into the n-th batch, as values of the same
+        // node would be compacted within a single event
+        let mut batches: Vec<Vec<Update>> = vec![];
+        let metadata = self.metadata.lock().unwrap();
         for item in data_change_items {
             log::debug!("Change: {item:?}");
-            let address = address(
-                &self.connection,
-                &self.subscription,
-                &item.item_to_monitor().node_id,
-            );
-            for value in item.values() {
-                updates.push(Update::new(
-                    address.clone(),
+            let node_id = &item.item_to_monitor().node_id;
+            let name = self.name(node_id);
+            for (n, value) in item.values().iter().enumerate() {
+                if let Some(backfill) = &self.backfill {
+                    backfill.seen(&self.subscription, node_id, &name, value);
+                }
+                if batches.len() <= n {
+                    batches.push(Vec::with_capacity(data_change_items.len()));
+                }
+                batches[n].push(value_update(
                     &self.connection,
-                    value.clone().to_json(),
+                    &self.subscription,
+                    node_id,
+                    &name,
+                    value.clone(),
+                    metadata.get(node_id),
+                    &self.data_types,
                 ));
             }
         }
 
-        self.sender.update_sync(updates);
+        self.sender.events_sync(batches);
+    }
+
+    fn on_event(&mut self, events: &EventNotificationList) {
+        let address = event_address(&self.connection, &self.subscription);
+
+        // all events share the same address, so each one must be sent as its own event
+        let batches = events
+            .events
+            .iter()
+            .flatten()
+            .map(|event| {
+                log::debug!("Event: {event:?}");
+                vec![Update::new(
+                    address.clone(),
+                    &self.connection,
+                    events::to_json(&self.event_fields, event, &self.data_types),
+                )]
+            })
+            .collect::<Vec<_>>();
+
+        self.sender.events_sync(batches);
     }
 }
 
 impl OnConnectionStatusChange for ConnectionEventSender {
     fn on_connection_status_change(&mut self, connected: bool) {
         if connected {
-            self.sender
-                .update_sync([connection_state(&self.connection, now(), StatusCode::Good)]);
+            self.sender.update_sync([connection_state(
+                &self.connection,
+                &self.endpoint,
+                now(),
+                StatusCode::Good,
+            )]);
+            self.reconnected.unbounded_send(()).ok();
+        } else {
+            self.backfill.disconnected();
         }
     }
 }
 
 impl OnSessionClosed for ConnectionEventSender {
     fn on_session_closed(&mut self, status_code: StatusCode) {
+        self.backfill.disconnected();
+
         let now = now();
 
         let mut updates = vec![];
 
         // notify connection
-        updates.push(connection_state(&self.connection, now.clone(), status_code));
+        updates.push(connection_state(
+            &self.connection,
+            &self.endpoint,
+            now.clone(),
+            status_code,
+        ));
 
         // notify items
-        for (id, sub) in &self.subscriptions {
+        for (id, sub) in self.subscriptions.lock().unwrap().iter() {
             for node in sub {
                 let address = address(&self.connection, &id, &node);
                 updates.push(Update::new(
@@ -209,7 +406,14 @@ impl OpcUaConnector {
         event: Event,
     ) {
         for update in event.updates {
-            if let Some(commands) = connections.get_mut(&update.channel) {
+            if update.channel.is_empty() {
+                // commands without a channel are meant for all connections
+                for commands in connections.values_mut() {
+                    if let Err(_) = commands.send(update.clone()).await {
+                        log::warn!("Failed to queue command");
+                    }
+                }
+            } else if let Some(commands) = connections.get_mut(&update.channel) {
                 if let Err(_) = commands.send(update).await {
                     log::warn!("Failed to queue command");
                 }
@@ -220,7 +424,27 @@ impl OpcUaConnector {
 
 impl OpcUaConnection {
     pub fn new(id: String, config: Connection) -> Self {
-        Self { id, config }
+        let node_ids = resolve::NodeIds::default();
+        let data_types = structures::DataTypes::new(
+            config.decode_structures,
+            config.json_encoding,
+            node_ids.clone(),
+        );
+        Self {
+            id,
+            config,
+            discovered: Default::default(),
+            node_names: Default::default(),
+            subscriptions: Default::default(),
+            backfill: Default::default(),
+            node_ids,
+            poller: poll::Poller::new(data_types.clone()),
+            retry: retry::Retry::new(node_ids.clone(), data_types.clone()),
+            data_types,
+            session: Default::default(),
+            stop: Default::default(),
+            stopped: Default::default(),
+        }
     }
 
     pub async fn command(&self, update: Update) {
@@ -234,6 +458,11 @@ impl OpcUaConnection {
     ) -> anyhow::Result<()> {
         log::debug!("Creating subscriptions");
 
+        // nodes of a previous session are polled, or retried, no more
+        self.poller = poll::Poller::new(self.data_types.clone());
+        self.retry = retry::Retry::new(self.node_ids.clone(), self.data_types.clone());
+        self.subscriptions = Default::default();
+
         #[cfg(feature = "opcua_0_11")]
         let session = session.read();
         #[cfg(not(feature = "opcua_0_11"))]
@@ -253,53 +482,434 @@ impl OpcUaConnection {
         subscription: &Subscription,
         tx: &mut EventSender,
     ) -> anyhow::Result<()> {
-        let subscription_id = session.create_subscription(
-            subscription.publish_interval.as_millis() as f64,
-            10,
-            30,
-            0,
-            0,
-            true,
-            SubscriptionEventSender {
-                connection: self.id.to_string(),
-                subscription: id.to_string(),
-                sender: tx.clone(),
-            },
-        )?;
+        let mut nodes = subscription.nodes.clone();
+
+        if let Some(browse) = &subscription.browse {
+            match browse::discover(session, &self.node_ids, browse) {
+                Ok(discovered) => {
+                    self.discovered.lock().unwrap().insert(
+                        id.to_string(),
+                        discovered.iter().map(|node| node.id.clone()).collect(),
+                    );
+                    nodes.extend(self.add_discovered_names(id, discovered));
+                }
+                Err(err) => log::warn!("Failed to discover nodes of subscription {id}: {err}"),
+            }
+        }
+
+        self.create_subscription(
+            session,
+            id,
+            subscription,
+            &nodes,
+            subscription.events.as_ref(),
+            tx,
+        )
+    }
+
+    /// The node IDs of all configured subscription nodes.
+    fn configured_node_ids(&self) -> impl Iterator<Item = &str> {
+        self.config
+            .subscriptions
+            .values()
+            .flat_map(|subscription| subscription.nodes.iter().map(|node| node.id.as_str()))
+    }
 
-        log::debug!("Created a subscription with id = {}", subscription_id);
+    /// Register the names of nodes discovered for a subscription.
+    ///
+    /// The aliases of discovered nodes are browse paths, which may collide with the names of
+    /// other nodes. Those nodes are dropped, so that each name refers to a single node.
+    fn add_discovered_names(&self, id: &str, nodes: Vec<Node>) -> Vec<Node> {
+        let mut node_names = self.node_names.lock().unwrap();
+        let names = node_names.entry(id.to_string()).or_default();
+
+        nodes
+            .into_iter()
+            .filter(|node| {
+                let name = node.name();
+                if names.contains(&name) {
+                    log::warn!(
+                        "Ignoring discovered node {} of subscription {id}, its name is taken: {name}",
+                        node.id
+                    );
+                    return false;
+                }
+                names.push(name);
+                true
+            })
+            .collect()
+    }
+
+    /// Browse again, and subscribe to nodes which have been added since the last discovery.
+    ///
+    /// As existing subscriptions are kept by the session, new nodes are added to those.
+    fn rediscover(&self, session: &Session, tx: &mut EventSender) -> anyhow::Result<()> {
+        for (id, subscription) in &self.config.subscriptions {
+            let browse = match &subscription.browse {
+                Some(browse) => browse,
+                None => continue,
+            };
+
+            let nodes = match browse::discover(session, &self.node_ids, browse) {
+                Ok(nodes) => nodes,
+                Err(err) => {
+                    log::warn!("Failed to re-discover nodes of subscription {id}: {err}");
+                    continue;
+                }
+            };
+
+            let nodes = {
+                let mut discovered = self.discovered.lock().unwrap();
+                let discovered = discovered.entry(id.to_string()).or_default();
+                nodes
+                    .into_iter()
+                    .filter(|node| discovered.insert(node.id.clone()))
+                    .collect::<Vec<_>>()
+            };
+
+            let nodes = self.add_discovered_names(id, nodes);
+            if nodes.is_empty() {
+                continue;
+            }
+
+            log::info!("Discovered {} new nodes for subscription {id}", nodes.len());
+
+            self.create_subscription(session, id, subscription, &nodes, None, tx)?;
+        }
+
+        Ok(())
+    }
+
+    /// Monitor the nodes again, whose browse paths resolve to a different node than before.
+    fn repoint(
+        &self,
+        session: &Session,
+        paths: &[String],
+        tx: &mut EventSender,
+    ) -> anyhow::Result<()> {
+        if paths.is_empty() {
+            return Ok(());
+        }
+
+        for (id, subscription) in &self.config.subscriptions {
+            let nodes = subscription
+                .nodes
+                .iter()
+                .filter(|node| paths.contains(&node.id))
+                .cloned()
+                .collect::<Vec<_>>();
+
+            if nodes.is_empty() {
+                continue;
+            }
+
+            log::info!(
+                "Browse paths of {} nodes of subscription {id} changed",
+                nodes.len()
+            );
+
+            for node in &nodes {
+                self.remove_node(session, id, &node.name());
+            }
+            self.create_subscription(session, id, subscription, &nodes, None, tx)?;
+        }
+
+        Ok(())
+    }
+
+    /// Stop monitoring (or polling) a node of a subscription, by name.
+    fn remove_node(&self, session: &Session, id: &str, name: &str) {
+        self.poller.remove(id, name);
+        self.retry.remove(id, name);
+
+        let subscribed = match self.subscriptions.lock().unwrap().get(id) {
+            Some(subscribed) => subscribed.clone(),
+            None => return,
+        };
+
+        if let Some(monitored_item_id) = subscribed.remove_node(name) {
+            if let Err(status) =
+                session.delete_monitored_items(subscribed.subscription_id, &[monitored_item_id])
+            {
+                log::info!("Failed to delete the monitored item of {name}: {status}");
+            }
+        }
+    }
+
+    /// Handle reconnects of the session, discovering new nodes and backfilling missed values.
+    async fn reconnect_loop(
+        self,
+        session: Arc<RwLock<Session>>,
+        reconnected: UnboundedReceiver<()>,
+        terminated: oneshot::Receiver<()>,
+        tx: EventSender,
+    ) {
+        let mut reconnected = reconnected.take_until(terminated);
+        while reconnected.next().await.is_some() {
+            let this = self.clone();
+            let session = session.clone();
+            let mut tx = tx.clone();
+            let result = spawn_blocking(move || {
+                #[cfg(feature = "opcua_0_11")]
+                let session = session.read();
+                #[cfg(not(feature = "opcua_0_11"))]
+                let session = session.read().unwrap();
+                let changed = this.node_ids.refresh(&session, this.configured_node_ids());
+                let result = this
+                    .repoint(&session, &changed, &mut tx)
+                    .and_then(|()| this.rediscover(&session, &mut tx));
+                this.backfill
+                    .run(&this.id, &session, None, &this.data_types, &mut tx);
+                result
+            })
+            .await;
+
+            match result {
+                Ok(Ok(())) => {}
+                Ok(Err(err)) => log::warn!("Failed to re-discover nodes: {err}"),
+                Err(err) => log::warn!("Failed to run discovery: {err}"),
+            }
+        }
+    }
+
+    fn create_subscription(
+        &self,
+        session: &Session,
+        id: &str,
+        subscription: &Subscription,
+        nodes: &[Node],
+        events: Option<&Events>,
+        tx: &mut EventSender,
+    ) -> anyhow::Result<()> {
+        if subscription.mode == SubscriptionMode::Poll {
+            return self.add_polled(session, id, subscription, nodes, events, tx);
+        }
+
+        // the source and types may use namespace URIs, or browse paths
+        let event_filter = events.map(|events| {
+            let source = self.node_ids.resolve(session, &events.source)?;
+            let types = events
+                .types
+                .iter()
+                .map(|t| self.node_ids.resolve(session, t))
+                .collect::<Result<Vec<_>, _>>()?;
+            Ok::<_, StatusCode>((source, events::Filter::new(events, &types)))
+        });
+
+        // nodes added later (e.g. by discovery) go to the existing subscription
+
+        let existing = self.subscriptions.lock().unwrap().get(id).cloned();
+        let subscribed = match existing {
+            Some(subscribed) => subscribed,
+            None => {
+                let nodes = Arc::new(Mutex::new(HashMap::with_capacity(nodes.len())));
+                let metadata = Arc::new(Mutex::new(HashMap::new()));
+
+                let subscription_id = session.create_subscription(
+                    subscription.publish_interval.as_millis() as f64,
+                    subscription.lifetime_count,
+                    subscription.max_keep_alive_count,
+                    subscription.max_notifications_per_publish,
+                    subscription.priority,
+                    subscription.publishing_enabled,
+                    SubscriptionEventSender {
+                        connection: self.id.to_string(),
+                        subscription: id.to_string(),
+                        sender: tx.clone(),
+                        nodes: nodes.clone(),
+                        metadata: metadata.clone(),
+                        data_types: self.data_types.clone(),
+                        event_fields: Arc::new(
+                            event_filter
+                                .as_ref()
+                                .and_then(|f| f.as_ref().ok())
+                                .map(|(_, f)| f.fields.clone())
+                                .unwrap_or_default(),
+                        ),
+                        backfill: subscription.backfill.then(|| self.backfill.clone()),
+                    },
+                )?;
+
+                log::debug!("Created a subscription with id = {}", subscription_id);
+
+                let subscribed = Subscribed {
+                    subscription_id,
+                    nodes,
+                    metadata,
+                    monitored_items: Default::default(),
+                };
+                self.subscriptions
+                    .lock()
+                    .unwrap()
+                    .insert(id.to_string(), subscribed.clone());
+                subscribed
+            }
+        };
+        let subscription_id = subscribed.subscription_id;
+
+        // parse nodes, and group them by their effective settings
+
+        let mut updates = Vec::new();
+        let mut failed = Vec::new();
+        let mut node_ids = Vec::with_capacity(nodes.len());
+        let mut groups = HashMap::<Timestamps, Vec<(Monitored, MonitoredItemCreateRequest)>>::new();
+        for node in nodes {
+            let name = node.name();
+            let monitoring = node.monitoring.or(&subscription.monitoring);
+
+            let node_id = match self.node_ids.resolve(session, &node.id) {
+                Ok(node_id) => node_id,
+                Err(status) => {
+                    log::info!("Unable to resolve {}: {status}", node.id);
+                    updates.push(unsubscribed(&self.id, id, &name, status));
+                    failed.push(retry::Failed::new(subscription_id, node, None, monitoring));
+                    continue;
+                }
+            };
+
+            // also for failed nodes, which may be subscribed when retrying
+            subscribed.add_node(session, &node_id, &name, subscription.metadata);
+            node_ids.push(node_id.clone());
+
+            let filter = match data_change_filter(session, &node_id, &monitoring) {
+                Ok(filter) => filter,
+                Err(status) => {
+                    log::info!("Unable to create filter for {node_id}: {status}");
+                    updates.push(unsubscribed(&self.id, id, &name, status));
+                    failed.push(retry::Failed::new(
+                        subscription_id,
+                        node,
+                        Some(node_id),
+                        monitoring,
+                    ));
+                    continue;
+                }
+            };
+
+            groups
+                .entry(monitoring.timestamps.unwrap_or_default())
+                .or_default()
+                .push((
+                    (node, node_id.clone(), monitoring.clone()),
+                    monitored_item(node_id, &monitoring, filter),
+                ));
+        }
+
+        // loaded before subscribing, as notifications can't use the session
+        self.data_types.load_variables(session, &node_ids);
 
         // Create some monitored items
-        let items_to_create: Vec<_> = subscription
-            .nodes
-            .iter()
-            .map(|node| NodeId::from_str(&node).map(|node| node.into()))
-            .collect::<Result<_, _>>()?;
 
-        let result = session.create_monitored_items(
+        // in batches, as servers limit the monitored items per call
+        let limit = services::operation_limit(
+            session,
+            VariableId::Server_ServerCapabilities_OperationLimits_MaxMonitoredItemsPerCall,
+        );
+        let batches = groups.into_iter().flat_map(|(timestamps, monitored)| {
+            services::batches(monitored, limit)
+                .into_iter()
+                .map(move |monitored| (timestamps, monitored))
+        });
+
+        let mut created = HashSet::new();
+        for (timestamps, monitored) in batches {
+            let (monitored, items_to_create): (Vec<_>, Vec<_>) = monitored.into_iter().unzip();
+
+            let result = session.create_monitored_items(
+                subscription_id,
+                timestamps.into(),
+                &items_to_create,
+            )?;
+
+            // the result has the same order as the request list
+
+            for ((node, node_id, monitoring), res) in monitored.into_iter().zip(result.into_iter())
+            {
+                let name = node.name();
+                // if the subscription was not good ...
+                if !res.status_code.is_good() {
+                    // ... we send that out.
+                    updates.push(unsubscribed(&self.id, id, &name, res.status_code));
+                    failed.push(retry::Failed::new(
+                        subscription_id,
+                        node,
+                        Some(node_id),
+                        monitoring,
+                    ));
+                } else {
+                    // ... otherwise, the subscription will provide a value
+                    subscribed
+                        .monitored_items
+                        .lock()
+                        .unwrap()
+                        .insert(name.clone(), res.monitored_item_id);
+                    created.insert(name);
+                }
+            }
+        }
+
+        // triggering and triggered nodes may have been added before
+        let mut linked = nodes.to_vec();
+        linked.extend(
+            subscription
+                .nodes
+                .iter()
+                .filter(|node| !nodes.iter().any(|candidate| candidate.id == node.id))
+                .cloned(),
+        );
+        let monitored_items = subscribed.monitored_items.lock().unwrap().clone();
+        relink_triggering(
+            session,
             subscription_id,
-            subscription.timestamps.into(),
-            &items_to_create,
-        )?;
+            &linked,
+            &monitored_items,
+            &created,
+        );
+
+        if subscription.retry_interval.is_some() {
+            self.retry.add(id, failed);
+        }
 
-        // the result has the same order as the request list
+        if let Some((events, filter)) = events.zip(event_filter) {
+            let status = match filter {
+                Ok((source, filter)) => {
+                    let mut request: MonitoredItemCreateRequest = source.into();
+                    request.item_to_monitor.attribute_id = AttributeId::EventNotifier as u32;
+
+                    let parameters = &mut request.requested_parameters;
+                    parameters.sampling_interval = 0.0;
+                    parameters.queue_size = events.queue_size;
+                    parameters.filter = ExtensionObject::from_encodable(
+                        ObjectId::EventFilter_Encoding_DefaultBinary,
+                        &filter.filter,
+                    );
+
+                    let result = session.create_monitored_items(
+                        subscription_id,
+                        TimestampsToReturn::Neither,
+                        &[request],
+                    )?;
+
+                    result
+                        .into_iter()
+                        .map(|res| res.status_code)
+                        .find(|status| !status.is_good())
+                }
+                Err(status) => Some(status),
+            };
 
-        let mut updates = Vec::with_capacity(result.len());
-        for (req, res) in items_to_create.into_iter().zip(result.into_iter()) {
-            // if the subscription was not good ...
-            if !res.status_code.is_good() {
-                // ... we send that out.
+            if let Some(status) = status {
+                log::info!("Failed to subscribe to events: {status}");
                 updates.push(Update::new(
-                    address(&self.id, id, &req.item_to_monitor.node_id),
+                    event_address(&self.id, id),
                     &self.id,
                     json!({
                         "subscribed": false,
                         "timestamp": now(),
-                        "status": res.status_code.name(),
+                        "status": status.name(),
                     }),
                 ));
             }
-            // ... otherwise, the subscription will provide a value
         }
 
         // send subscription events
@@ -311,30 +921,208 @@ impl OpcUaConnection {
         Ok(())
     }
 
+    /// Add nodes to a subscription in poll mode.
+    fn add_polled(
+        &self,
+        session: &Session,
+        id: &str,
+        subscription: &Subscription,
+        nodes: &[Node],
+        events: Option<&Events>,
+        tx: &mut EventSender,
+    ) -> anyhow::Result<()> {
+        if events.is_some() {
+            log::warn!("Events are not supported by subscription {id}, in poll mode");
+        }
+
+        let mut updates = Vec::new();
+        let nodes = nodes
+            .iter()
+            .filter_map(|node| match self.node_ids.resolve(session, &node.id) {
+                Ok(node_id) => {
+                    let metadata = subscription
+                        .metadata
+                        .then(|| metadata::read(session, &node_id))
+                        .flatten();
+                    Some((node_id, node.name(), metadata))
+                }
+                Err(status) => {
+                    log::info!("Unable to resolve {}: {status}", node.id);
+                    updates.push(unsubscribed(&self.id, id, &node.name(), status));
+                    None
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let node_ids = nodes
+            .iter()
+            .map(|(node_id, _, _)| node_id.clone())
+            .collect::<Vec<_>>();
+        self.data_types.load_variables(session, &node_ids);
+
+        self.poller.add(id, nodes);
+
+        tx.update_sync(updates);
+
+        Ok(())
+    }
+
     pub fn start(self, tx: EventSender) -> impl Sink<Update> {
         let (cmd_tx, cmd_rx) = channel::<Update>(1_000);
 
-        Handle::current().spawn_blocking(move || {
-            if let Err(err) = self.do_run(tx, cmd_rx) {
-                log::error!("Failed to run OPC connection: {err}");
-            }
+        let this = self.clone();
+        let handler = CommandHandler::new(
+            self.id.clone(),
+            tx.clone(),
+            self.backfill.clone(),
+            self.node_ids.clone(),
+            self.data_types.clone(),
+        );
+        spawn(async move {
+            this.command_loop(cmd_rx, handler).await;
+            log::warn!("Command loop exited");
+            this.stop();
         });
 
+        Handle::current().spawn_blocking(move || self.supervise(tx));
+
         cmd_tx
     }
 
-    fn do_run(
-        mut self,
-        mut tx: EventSender,
-        commands: impl Stream<Item = Update> + Send + 'static,
-    ) -> anyhow::Result<()> {
-        let pki_dir = std::env::var_os("PKI_DIR")
-            .map(|p| PathBuf::from(p))
+    /// Stop the connection, terminating the current session (if any).
+    fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        if let Some(stop) = self.stop.lock().unwrap().take() {
+            stop.send(SessionCommand::Stop).ok();
+        }
+    }
+
+    /// Run the connection, restarting it with a backoff when it fails or terminates.
+    fn supervise(self, mut tx: EventSender) {
+        let urls = self.config.urls();
+        let mut current = 0;
+        let mut attempt = 0;
+
+        while !self.stopped.load(Ordering::SeqCst) {
+            attempt += 1;
+            let url = urls[current];
+            log::info!("Connecting {} to {url} (attempt {attempt})", self.id);
+
+            let result = self.clone().do_run(url, tx.clone());
+            if self.stopped.load(Ordering::SeqCst) {
+                break;
+            }
+
+            let (status, error) = match result {
+                Ok(()) => {
+                    log::warn!("Session loop of {} exited", self.id);
+                    // we had been connected, so start over with the backoff
+                    attempt = 0;
+                    (StatusCode::BadConnectionClosed, None)
+                }
+                Err(err) => {
+                    log::error!("Failed to run OPC connection {}: {err}", self.id);
+                    let status = err
+                        .downcast_ref::<StatusCode>()
+                        .copied()
+                        .unwrap_or(StatusCode::BadCommunicationError);
+                    (status, Some(err.to_string()))
+                }
+            };
+
+            let delay = self.config.reconnect.delay(attempt.max(1), rand::random());
+            log::info!(
+                "Restarting {} in {}",
+                self.id,
+                humantime::format_duration(delay)
+            );
+
+            let mut update = connection_state(&self.id, url, now(), status);
+            if let Value::Object(value) = &mut update.value {
+                value.insert("attempt".to_string(), attempt.into());
+                value.insert(
+                    "retryIn".to_string(),
+                    humantime::format_duration(delay).to_string().into(),
+                );
+                if let Some(error) = error {
+                    value.insert("error".to_string(), error.into());
+                }
+            }
+            tx.update_blocking([update]);
+
+            std::thread::sleep(delay);
+
+            if urls.len() > 1 {
+                current = self.failover(&urls, current);
+                log::info!("Failing over {} to {}", self.id, urls[current]);
+            }
+        }
+
+        log::info!("Connection {} stopped", self.id);
+
+        tx.0.close_channel();
+    }
+
+    /// Select the endpoint to use next, after the current one failed.
+    ///
+    /// Without checking the service level, this is the next endpoint in the list. Otherwise, it is
+    /// the one reporting the highest service level, preferring the next ones on a tie.
+    fn failover(&self, urls: &[&str], failed: usize) -> usize {
+        let next = (failed + 1) % urls.len();
+        if !self.config.check_service_level {
+            return next;
+        }
+
+        let mut best: Option<(usize, u8)> = None;
+        for i in (0..urls.len()).map(|i| (next + i) % urls.len()) {
+            match self.probe_service_level(urls[i]) {
+                Ok(level) => {
+                    log::info!("Service level of {}: {level}", urls[i]);
+                    if best.map(|(_, best)| level > best).unwrap_or(true) {
+                        best = Some((i, level));
+                    }
+                }
+                Err(err) => log::info!("Failed to probe {}: {err}", urls[i]),
+            }
+        }
+
+        best.map(|(i, _)| i).unwrap_or(next)
+    }
+
+    /// Connect to an endpoint, just for reading its service level.
+    fn probe_service_level(&self, url: &str) -> anyhow::Result<u8> {
+        let (session, _) = self.connect(url)?;
+
+        #[cfg(feature = "opcua_0_11")]
+        let mut session = session.write();
+        #[cfg(not(feature = "opcua_0_11"))]
+        let mut session = session.write().unwrap();
+
+        let level = read_service_level(&session);
+        session.disconnect();
+
+        Ok(level?)
+    }
+
+    /// Create a session with an endpoint.
+    fn connect(&self, url: &str) -> anyhow::Result<(Arc<RwLock<Session>>, endpoints::Endpoint)> {
+        let pki_dir = self
+            .config
+            .pki_dir
+            .clone()
+            .or_else(|| std::env::var_os("PKI_DIR").map(|p| PathBuf::from(p)))
             .unwrap_or_else(|| std::env::temp_dir().join("drogue-opcua-agent").join("pki"));
 
-        let client = ClientBuilder::new()
+        pki::trust_certificates(&pki_dir, &self.config.trusted_server_certificates)?;
+
+        let mut client = ClientBuilder::new()
             .application_name("Drogue IoT OPC UA Agent")
-            .application_uri("https://drogue.io")
+            .application_uri(
+                self.config
+                    .application_uri
+                    .as_deref()
+                    .unwrap_or("https://drogue.io"),
+            )
             .product_uri("https://drogue.io")
             .trust_server_certs(self.config.auto_accept_server_certificate)
             .create_sample_keypair(self.config.create_sample_keypair)
@@ -344,101 +1132,257 @@ impl OpcUaConnection {
                     .unwrap_or_else(|| Duration::from_secs(15))
                     .as_millis() as _,
             )
-            .session_retry_limit(
-                self.config
-                    .session_retry_limit
-                    .map(|r| r as i32)
-                    .unwrap_or(-1),
-            )
-            .pki_dir(pki_dir);
+            .session_retry_limit(self.config.session_retry_limit())
+            .pki_dir(pki_dir.clone());
+
+        if let Some(certificate) = &self.config.client_certificate {
+            client = client.certificate_path(certificate);
+        }
+        if let Some(private_key) = &self.config.client_private_key {
+            client = client.private_key_path(private_key);
+        }
 
         let id = match &self.config.credentials {
             Credentials::Anonymous => IdentityToken::Anonymous,
             Credentials::User { username, password } => {
                 IdentityToken::UserName(username.to_string(), password.to_string())
             }
+            Credentials::X509 {
+                user_certificate,
+                user_private_key,
+            } => IdentityToken::X509(user_certificate.clone(), user_private_key.clone()),
+            // the client library can only activate sessions with anonymous, user name, or
+            // X.509 identities
+            Credentials::IssuedToken { token_file } => bail!(
+                "Issued token identities ({}) are not supported by the OPC UA client",
+                token_file.display()
+            ),
         };
 
         let mut client = client
             .client()
             .ok_or_else(|| anyhow!("Invalid configuration"))?;
 
-        let security_mode = match self.config.security_mode.as_str() {
-            "None" | "none" => MessageSecurityMode::None,
-            "Sign" | "sign" => MessageSecurityMode::Sign,
-            "SignAndEncrypt" | "signAndEncrypt" => MessageSecurityMode::SignAndEncrypt,
-            _ => bail!("Invalid security mode. Must be: none, sign, or signAndEncrypt"),
-        };
+        if let Some(thumbprint) = &self.config.server_certificate_thumbprint {
+            pki::pin_server_certificate(&client, url, &pki_dir, thumbprint)?;
+        }
+
+        let endpoint = endpoints::select(&client, url, &self.config)?;
+        log::info!(
+            "Connecting to {url} using: {:?}/{:?}",
+            endpoint.security_policy,
+            endpoint.security_mode
+        );
 
         let session = client.connect_to_endpoint(
             (
-                self.config.url.as_str(),
-                self.config.security_policy.as_str(),
-                security_mode,
+                url,
+                endpoint.security_policy.uri(),
+                endpoint.security_mode.into(),
                 None,
             ),
             id,
         )?;
 
+        Ok((session, endpoint))
+    }
+
+    fn do_run(mut self, url: &str, mut tx: EventSender) -> anyhow::Result<()> {
+        let (session, endpoint) = self.connect(url)?;
+
+        tx.update_sync([Update::new(
+            ["opcua", self.id.as_str(), "endpoint"],
+            &self.id,
+            json!({
+                "timestamp": now(),
+                "url": url,
+                "securityPolicy": format!("{:?}", endpoint.security_policy),
+                "securityMode": format!("{:?}", endpoint.security_mode),
+                "securityLevel": endpoint.security_level,
+            }),
+        )]);
+
+        let (reconnected_tx, reconnected_rx) = unbounded();
+
         {
             #[cfg(feature = "opcua_0_11")]
             let mut session = session.write();
             #[cfg(not(feature = "opcua_0_11"))]
             let mut session = session.write().unwrap();
 
+            // discovered nodes are added when subscribing
+            *self.node_names.lock().unwrap() = self
+                .config
+                .subscriptions
+                .iter()
+                .map(|(id, subs)| {
+                    (
+                        id.to_string(),
+                        subs.nodes.iter().map(Node::name).collect::<Vec<_>>(),
+                    )
+                })
+                .collect();
+
             let sender = ConnectionEventSender {
                 connection: self.id.clone(),
+                endpoint: url.to_string(),
                 sender: tx.clone(),
-                // we parse and re-encode the node id to have a normalized form
-                subscriptions: self
-                    .config
-                    .subscriptions
-                    .iter()
-                    .map(|(id, subs)| {
-                        (
-                            id.to_string(),
-                            subs.nodes
-                                .iter()
-                                .map(|n| {
-                                    NodeId::from_str(n)
-                                        .map(|id| id.to_string())
-                                        .unwrap_or_else(|_| n.to_string())
-                                })
-                                .collect::<Vec<_>>(),
-                        )
-                    })
-                    .collect(),
+                subscriptions: self.node_names.clone(),
+                reconnected: reconnected_tx,
+                backfill: self.backfill.clone(),
             };
             session.set_connection_status_callback(sender.clone());
             session.set_session_closed_callback(sender);
         }
 
-        tx.update_sync([connection_state(&self.id, now(), StatusCode::Good)]);
+        let mut state = connection_state(&self.id, url, now(), StatusCode::Good);
+        if self.config.check_service_level {
+            #[cfg(feature = "opcua_0_11")]
+            let session = session.read();
+            #[cfg(not(feature = "opcua_0_11"))]
+            let session = session.read().unwrap();
+
+            if let Value::Object(value) = &mut state.value {
+                if let Ok(level) = read_service_level(&session) {
+                    value.insert("serviceLevel".to_string(), level.into());
+                }
+                if let Ok(Some(uris)) = services::read_value(
+                    &session,
+                    &VariableId::Server_ServerRedundancy_ServerUriArray.into(),
+                ) {
+                    value.insert("redundantServers".to_string(), uris.to_json());
+                }
+            }
+        }
+        tx.update_sync([state]);
+
+        {
+            #[cfg(feature = "opcua_0_11")]
+            let session = session.read();
+            #[cfg(not(feature = "opcua_0_11"))]
+            let session = session.read().unwrap();
+
+            // node IDs may resolve differently, and data types may differ, for a different server
+            self.node_ids.refresh(&session, self.configured_node_ids());
+            self.data_types.clear();
+        }
 
         self.subscribe(session.clone(), tx.clone())?;
 
+        // dropped when the session terminates
+        let (_terminated, terminated_rx) = oneshot::channel();
+
+        if self
+            .config
+            .subscriptions
+            .values()
+            .any(|subscription| subscription.browse.is_some() || subscription.backfill)
+            || self.configured_node_ids().any(resolve::is_browse_path)
+        {
+            spawn(self.clone().reconnect_loop(
+                session.clone(),
+                reconnected_rx,
+                terminated_rx,
+                tx.clone(),
+            ));
+        }
+
+        if self
+            .config
+            .subscriptions
+            .values()
+            .any(|subscription| subscription.backfill)
+        {
+            // backfill the values missed while restarting the connection
+            let this = self.clone();
+            let session = session.clone();
+            let mut tx = tx.clone();
+            spawn_blocking(move || {
+                #[cfg(feature = "opcua_0_11")]
+                let session = session.read();
+                #[cfg(not(feature = "opcua_0_11"))]
+                let session = session.read().unwrap();
+                this.backfill
+                    .run(&this.id, &session, None, &this.data_types, &mut tx);
+            });
+        }
+
+        for (id, subscription) in &self.config.subscriptions {
+            if subscription.mode != SubscriptionMode::Poll {
+                continue;
+            }
+
+            let this = self.clone();
+            let session = session.clone();
+            let id = id.clone();
+            let subscription = subscription.clone();
+            let tx = tx.clone();
+            spawn_blocking(move || {
+                let backfill = subscription.backfill.then(|| this.backfill.clone());
+                this.poller
+                    .run(&this.id, session, &id, &subscription, backfill, tx);
+            });
+        }
+
+        for (id, subscription) in &self.config.subscriptions {
+            if subscription.retry_interval.is_none()
+                || subscription.mode != SubscriptionMode::Subscribe
+            {
+                continue;
+            }
+            let subscribed = match self.subscriptions.lock().unwrap().get(id) {
+                Some(subscribed) => subscribed.clone(),
+                None => continue,
+            };
+
+            let this = self.clone();
+            let session = session.clone();
+            let id = id.clone();
+            let subscription = subscription.clone();
+            let tx = tx.clone();
+            spawn_blocking(move || {
+                this.retry
+                    .run(&this.id, session, &id, &subscription, subscribed, tx)
+            });
+        }
+
+        // set when the session terminates
+        let terminated = Arc::new(AtomicBool::new(false));
+
+        if let Some(monitoring) = self.config.monitor_server.clone() {
+            let id = self.id.clone();
+            let session = session.clone();
+            let terminated = terminated.clone();
+            let tx = tx.clone();
+            spawn_blocking(move || health::monitor(&id, session, &monitoring, &terminated, tx));
+        }
+
         let (session_tx, rx) = oneshot::channel();
 
-        let cmd_session = session.clone();
-        spawn(async move {
-            Self::command_loop(cmd_session, commands).await;
-            log::warn!("Command loop exited");
-            session_tx.send(SessionCommand::Stop).ok();
-        });
+        *self.session.lock().unwrap() = Some(session.clone());
+        *self.stop.lock().unwrap() = Some(session_tx);
+        if self.stopped.load(Ordering::SeqCst) {
+            // stopped while we were connecting
+            self.stop();
+        }
 
         // the next call will block, until the session loop terminates
         Session::run_loop(session, 10, rx);
 
-        log::warn!("Session loop exited. Terminating...");
+        log::warn!("Session loop exited");
 
-        tx.0.close_channel();
+        self.poller.stop();
+        self.retry.stop();
+        terminated.store(true, Ordering::SeqCst);
+        self.session.lock().unwrap().take();
+        self.stop.lock().unwrap().take();
 
         // done
 
         Ok(())
     }
 
-    async fn command_loop(session: Arc<RwLock<Session>>, commands: impl Stream<Item = Update>) {
+    async fn command_loop(&self, commands: impl Stream<Item = Update>, handler: CommandHandler) {
         let mut commands = Box::pin(commands);
         loop {
             match commands.next().await {
@@ -447,55 +1391,28 @@ impl OpcUaConnection {
                     break;
                 }
                 Some(update) => {
-                    let session = session.clone();
+                    let session = match self.session.lock().unwrap().clone() {
+                        Some(session) => session,
+                        None => {
+                            log::info!("Not connected, rejecting command: {update:?}");
+                            handler
+                                .clone()
+                                .reject(update, StatusCode::BadServerNotConnected);
+                            continue;
+                        }
+                    };
+                    let mut handler = handler.clone();
                     spawn_blocking(move || {
                         #[cfg(feature = "opcua_0_11")]
                         let session = session.read();
                         #[cfg(not(feature = "opcua_0_11"))]
                         let session = session.read().unwrap();
-                        Self::write_command(&session, update);
+                        handler.handle(&session, update);
                     });
                 }
             }
         }
     }
-
-    fn write_command(session: &Session, update: Update) {
-        log::debug!("Writing command: {update:?}");
-
-        let node_id = update
-            .extensions
-            .get("nodeId")
-            .and_then(|id| id.as_str())
-            .or_else(|| update.address.last().map(|s| s.as_str()));
-
-        let node_id = if let Some(node_id) = node_id {
-            node_id
-        } else {
-            return;
-        };
-
-        let node_id = match NodeId::from_str(&node_id) {
-            Ok(node_id) => node_id,
-            Err(err) => {
-                log::info!("Failed to parse NodeId: {err}");
-                return;
-            }
-        };
-
-        let value = DataValue::value_only(update.value.into_variant());
-
-        let value = WriteValue {
-            node_id,
-            attribute_id: AttributeId::Value as u32,
-            index_range: Default::default(),
-            value,
-        };
-
-        if let Err(err) = session.write(&[value]) {
-            log::info!("Failed to write: {err}");
-        }
-    }
 }
 
 pub trait IntoVariant {
@@ -535,6 +1452,174 @@ impl IntoVariant for Value {
     }
 }
 
+/// Create the data change filter for a monitored item, if one is required.
+fn data_change_filter(
+    session: &Session,
+    node_id: &NodeId,
+    monitoring: &MonitoringSettings,
+) -> Result<Option<DataChangeFilter>, StatusCode> {
+    let (deadband_type, deadband_value) = match monitoring.deadband {
+        None => (DeadbandType::None, 0.0),
+        Some(Deadband::Absolute(value)) => (DeadbandType::Absolute, value),
+        Some(Deadband::Percent(value)) => {
+            // the server calculates the deadband from the EURange, so the node must have one
+            match services::read_eu_range(session, node_id)? {
+                Some(range) => {
+                    log::debug!("EURange of {node_id}: {} - {}", range.low, range.high);
+                }
+                None => {
+                    log::info!(
+                        "Percent deadband requires an EURange, which {node_id} does not have"
+                    );
+                    return Err(StatusCode::BadFilterNotAllowed);
+                }
+            }
+            (DeadbandType::Percent, value)
+        }
+    };
+
+    if monitoring.trigger.is_none() && monitoring.deadband.is_none() {
+        // use the server's default
+        return Ok(None);
+    }
+
+    Ok(Some(DataChangeFilter {
+        trigger: monitoring.trigger.unwrap_or_default().into(),
+        deadband_type: deadband_type as u32,
+        deadband_value,
+    }))
+}
+
+/// A node to be monitored, with its resolved ID and effective monitoring settings.
+type Monitored<'a> = (&'a Node, NodeId, MonitoringSettings);
+
+/// Create the request for a monitored item, applying the monitoring settings.
+fn monitored_item(
+    node_id: NodeId,
+    monitoring: &MonitoringSettings,
+    filter: Option<DataChangeFilter>,
+) -> MonitoredItemCreateRequest {
+    let mut request: MonitoredItemCreateRequest = node_id.into();
+
+    let parameters = &mut request.requested_parameters;
+    if let Some(sampling_interval) = monitoring.sampling_interval {
+        parameters.sampling_interval = sampling_interval.as_millis() as f64;
+    }
+    if let Some(queue_size) = monitoring.queue_size {
+        parameters.queue_size = queue_size;
+    }
+    if let Some(discard_oldest) = monitoring.discard_oldest {
+        parameters.discard_oldest = discard_oldest;
+    }
+    if let Some(filter) = filter {
+        parameters.filter = ExtensionObject::from_encodable(
+            ObjectId::DataChangeFilter_Encoding_DefaultBinary,
+            &filter,
+        );
+    }
+    if let Some(monitoring_mode) = monitoring.monitoring_mode {
+        request.monitoring_mode = monitoring_mode.into();
+    }
+
+    request
+}
+
+/// Link the items triggered by a node to its monitored item.
+///
+/// Triggered nodes are referenced by their ID or alias. Failures are only logged, as the
+/// triggered items still report according to their own monitoring mode.
+fn set_triggering(
+    session: &Session,
+    subscription_id: u32,
+    node: &Node,
+    nodes: &[Node],
+    monitored_items: &HashMap<String, u32>,
+) {
+    let lookup = |reference: &str| {
+        triggered_node(reference, nodes)
+            .and_then(|candidate| monitored_items.get(&candidate.name()))
+            .copied()
+    };
+
+    let triggering_item_id = match monitored_items.get(&node.name()) {
+        Some(id) => *id,
+        None => return,
+    };
+
+    let mut links_to_add = Vec::with_capacity(node.triggered_nodes.len());
+    for reference in &node.triggered_nodes {
+        match lookup(reference) {
+            Some(id) => links_to_add.push(id),
+            None => log::info!("Unknown triggered node '{reference}' of {}", node.id),
+        }
+    }
+
+    if links_to_add.is_empty() {
+        return;
+    }
+
+    match session.set_triggering(subscription_id, triggering_item_id, &links_to_add, &[]) {
+        Ok((Some(results), _)) => {
+            for (link, status) in links_to_add.iter().zip(results) {
+                if !status.is_good() {
+                    log::info!(
+                        "Failed to add triggering link {link} of {}: {status}",
+                        node.id
+                    );
+                }
+            }
+        }
+        Ok((None, _)) => {}
+        Err(status) => log::info!("Failed to set triggering of {}: {status}", node.id),
+    }
+}
+
+/// Find a triggered node, by its ID or alias.
+fn triggered_node<'a>(reference: &str, nodes: &'a [Node]) -> Option<&'a Node> {
+    let node_id = NodeId::from_str(reference).ok();
+    nodes.iter().find(|candidate| {
+        candidate.alias.as_deref() == Some(reference)
+            || candidate.id == reference
+            || (node_id.is_some() && NodeId::from_str(&candidate.id).ok() == node_id)
+    })
+}
+
+/// Link the new monitored items of nodes (by name), to and from the items triggering them.
+fn relink_triggering(
+    session: &Session,
+    subscription_id: u32,
+    nodes: &[Node],
+    monitored_items: &HashMap<String, u32>,
+    created: &HashSet<String>,
+) {
+    for node in nodes.iter().filter(|node| !node.triggered_nodes.is_empty()) {
+        if created.contains(&node.name()) {
+            // the triggering item is new, so it has no links yet
+            set_triggering(session, subscription_id, node, nodes, monitored_items);
+            continue;
+        }
+
+        let triggered_nodes = node
+            .triggered_nodes
+            .iter()
+            .filter(|reference| {
+                triggered_node(reference, nodes)
+                    .map(|triggered| created.contains(&triggered.name()))
+                    .unwrap_or_default()
+            })
+            .cloned()
+            .collect::<Vec<_>>();
+
+        if !triggered_nodes.is_empty() {
+            let node = Node {
+                triggered_nodes,
+                ..node.clone()
+            };
+            set_triggering(session, subscription_id, &node, nodes, monitored_items);
+        }
+    }
+}
+
 fn address<N>(connection: &str, subscription: &str, node_id: &N) -> Address
 where
     N: ToString,
@@ -549,22 +1634,108 @@ where
     .into()
 }
 
+fn event_address(connection: &str, subscription: &str) -> Address {
+    vec![
+        "opcua".to_string(),
+        connection.to_string(),
+        "events".to_string(),
+        subscription.to_string(),
+    ]
+    .into()
+}
+
+fn method_address(connection: &str, method_id: &NodeId) -> Address {
+    vec![
+        "opcua".to_string(),
+        connection.to_string(),
+        "methods".to_string(),
+        method_id.to_string(),
+    ]
+    .into()
+}
+
+/// Create the update for a value of a node.
+fn value_update(
+    connection: &str,
+    subscription: &str,
+    node_id: &NodeId,
+    name: &str,
+    value: DataValue,
+    metadata: Option<&Value>,
+    data_types: &structures::DataTypes,
+) -> Update {
+    let mut update = Update::new(
+        address(connection, subscription, name),
+        connection,
+        data_types.to_json(value),
+    );
+    update
+        .extensions
+        .insert("nodeId".to_string(), node_id.to_string().into());
+    if let Some(metadata) = metadata {
+        update
+            .extensions
+            .insert("metadata".to_string(), metadata.clone());
+    }
+    update
+}
+
+/// Read the service level of the server.
+fn read_service_level(session: &Session) -> Result<u8, StatusCode> {
+    match services::read_value(session, &VariableId::Server_ServiceLevel.into())? {
+        Some(Variant::Byte(level)) => Ok(level),
+        _ => Err(StatusCode::BadTypeMismatch),
+    }
+}
+
+/// An update, reporting that a previously failed node is subscribed now.
+fn subscribed(connection: &str, subscription: &str, name: &str) -> Update {
+    Update::new(
+        address(connection, subscription, &name),
+        connection,
+        json!({
+            "subscribed": true,
+            "timestamp": now(),
+        }),
+    )
+}
+
+/// An update, reporting that a node is not subscribed.
+fn unsubscribed(connection: &str, subscription: &str, name: &str, status: StatusCode) -> Update {
+    Update::new(
+        address(connection, subscription, &name),
+        connection,
+        json!({
+            "subscribed": false,
+            "timestamp": now(),
+            "status": status.name(),
+        }),
+    )
+}
+
 fn now() -> String {
     humantime::format_rfc3339_millis(SystemTime::now()).to_string()
 }
 
-fn connection_state(connection: &str, timestamp: String, code: StatusCode) -> Update {
+fn connection_state(
+    connection: &str,
+    endpoint: &str,
+    timestamp: String,
+    code: StatusCode,
+) -> Update {
     Update::new(
         ["opcua", connection, "connection"],
         connection,
         if code.is_good() {
             json!({
                 "connected": true,
+                "endpoint": endpoint,
                 "timestamp": timestamp,
             })
         } else {
             json!({
                 "connected": false,
+                "endpoint": endpoint,
                 "status": code.to_string(),
                 "timestamp": timestamp,
             })
@@ -0,0 +1,89 @@
+//! Management of trusted server certificates.
+
+use super::opcua::{
+    client::prelude::*,
+    crypto::{CertificateStore, X509},
+};
+use anyhow::{anyhow, bail, Context};
+use std::path::{Path, PathBuf};
+
+/// Add certificates to the trusted certificates of the PKI directory.
+pub fn trust_certificates(pki_dir: &Path, certificates: &[PathBuf]) -> anyhow::Result<()> {
+    for path in certificates {
+        let cert = CertificateStore::read_cert(path)
+            .map_err(|err| anyhow!("Failed to read certificate {}: {err}", path.display()))?;
+        trust(pki_dir, &cert)?;
+    }
+
+    Ok(())
+}
+
+/// Trust the certificate of the server, if it matches the pinned thumbprint.
+///
+/// This fails if none of the server's endpoints has a matching certificate.
+pub fn pin_server_certificate(
+    client: &Client,
+    url: &str,
+    pki_dir: &Path,
+    thumbprint: &str,
+) -> anyhow::Result<()> {
+    let expected = normalize_thumbprint(thumbprint);
+
+    let endpoints = client.get_server_endpoints_from_url(url)?;
+    for endpoint in endpoints {
+        if endpoint.server_certificate.is_null() {
+            continue;
+        }
+
+        let cert = match X509::from_byte_string(&endpoint.server_certificate) {
+            Ok(cert) => cert,
+            Err(status) => {
+                log::info!("Invalid certificate of {}: {status}", endpoint.endpoint_url);
+                continue;
+            }
+        };
+
+        if normalize_thumbprint(&cert.thumbprint().as_hex_string()) == expected {
+            log::info!("Trusting pinned server certificate of {url}");
+            return trust(pki_dir, &cert);
+        }
+    }
+
+    bail!("No server certificate of {url} matches the pinned thumbprint")
+}
+
+/// Store a certificate in the `trusted` folder of the PKI directory.
+fn trust(pki_dir: &Path, cert: &X509) -> anyhow::Result<()> {
+    let trusted = pki_dir.join("trusted");
+    std::fs::create_dir_all(&trusted)
+        .with_context(|| format!("Failed to create {}", trusted.display()))?;
+
+    let der = cert
+        .to_der()
+        .map_err(|_| anyhow!("Failed to encode certificate"))?;
+    let path = trusted.join(CertificateStore::cert_file_name(cert));
+    std::fs::write(&path, der).with_context(|| format!("Failed to write {}", path.display()))?;
+
+    Ok(())
+}
+
+/// Normalize a hex encoded thumbprint, dropping separators.
+fn normalize_thumbprint(thumbprint: &str) -> String {
+    thumbprint
+        .chars()
+        .filter(|c| c.is_ascii_hexdigit())
+        .map(|c| c.to_ascii_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_normalize_thumbprint() {
+        assert_eq!(normalize_thumbprint("0a1b2c"), "0a1b2c");
+        assert_eq!(normalize_thumbprint("0A:1B:2C"), "0a1b2c");
+        assert_eq!(normalize_thumbprint(" 0a 1b 2c "), "0a1b2c");
+    }
+}
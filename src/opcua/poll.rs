@@ -0,0 +1,268 @@
+//! Polling of values, for servers without (proper) subscription support.
+
+use super::{
+    history::Backfill, opcua::client::prelude::*, structures::DataTypes, value_update, EventSender,
+    RwLock, Subscription,
+};
+use crate::middleware::Update;
+use serde_json::Value;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// A polled node.
+struct Polled {
+    node_id: NodeId,
+    name: String,
+    /// The last value and status, for suppressing unchanged values.
+    last: Option<(Option<Variant>, Option<StatusCode>)>,
+    /// The metadata of the node, if enabled
+    metadata: Option<Value>,
+}
+
+/// The nodes of subscriptions in poll mode.
+#[derive(Clone)]
+pub struct Poller {
+    /// Polled nodes, by subscription
+    nodes: Arc<Mutex<HashMap<String, Vec<Polled>>>>,
+    /// Set when the session terminated
+    stopped: Arc<AtomicBool>,
+    /// Data types of the server, for decoding structures
+    data_types: DataTypes,
+}
+
+impl Poller {
+    pub fn new(data_types: DataTypes) -> Self {
+        Self {
+            nodes: Default::default(),
+            stopped: Default::default(),
+            data_types,
+        }
+    }
+
+    /// Add nodes to a subscription, picked up with the next poll.
+    pub fn add<I>(&self, subscription: &str, nodes: I)
+    where
+        I: IntoIterator<Item = (NodeId, String, Option<Value>)>,
+    {
+        self.nodes
+            .lock()
+            .unwrap()
+            .entry(subscription.to_string())
+            .or_default()
+            .extend(nodes.into_iter().map(|(node_id, name, metadata)| Polled {
+                node_id,
+                name,
+                last: None,
+                metadata,
+            }));
+    }
+
+    /// Remove a node of a subscription, by name.
+    pub fn remove(&self, subscription: &str, name: &str) {
+        if let Some(nodes) = self.nodes.lock().unwrap().get_mut(subscription) {
+            nodes.retain(|polled| polled.name != name);
+        }
+    }
+
+    /// Stop polling, as the session terminated.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    /// Poll the nodes of a subscription, until stopped.
+    ///
+    /// This blocks the current thread.
+    pub fn run(
+        &self,
+        connection: &str,
+        session: Arc<RwLock<Session>>,
+        id: &str,
+        subscription: &Subscription,
+        backfill: Option<Backfill>,
+        mut tx: EventSender,
+    ) {
+        let timestamps = subscription.monitoring.timestamps.unwrap_or_default();
+
+        loop {
+            std::thread::sleep(subscription.publish_interval);
+
+            if self.stopped.load(Ordering::SeqCst) || tx.0.is_closed() {
+                break;
+            }
+
+            // nodes may be removed while reading, so the values are matched with this snapshot
+            let read = self.snapshot(id);
+            if read.is_empty() {
+                continue;
+            }
+
+            let nodes_to_read = read
+                .iter()
+                .map(|(node_id, _)| ReadValueId {
+                    node_id: node_id.clone(),
+                    attribute_id: AttributeId::Value as u32,
+                    index_range: UAString::null(),
+                    data_encoding: QualifiedName::null(),
+                })
+                .collect::<Vec<_>>();
+
+            let values = {
+                #[cfg(feature = "opcua_0_11")]
+                let session = session.read();
+                #[cfg(not(feature = "opcua_0_11"))]
+                let session = session.read().unwrap();
+
+                if !session.is_connected() {
+                    continue;
+                }
+
+                session.read(&nodes_to_read, timestamps.into(), 0.0)
+            };
+
+            let values = match values {
+                Ok(values) => values,
+                Err(status) => {
+                    log::info!("Failed to poll subscription {id}: {status}");
+                    continue;
+                }
+            };
+
+            let updates = self.updates(
+                connection,
+                id,
+                subscription,
+                backfill.as_ref(),
+                read,
+                values,
+            );
+            if !updates.is_empty() {
+                tx.update_blocking(updates);
+            }
+        }
+
+        log::debug!("Stopped polling subscription {id}");
+    }
+
+    /// The IDs and names of the nodes of a subscription, in the order they are read.
+    fn snapshot(&self, id: &str) -> Vec<(NodeId, String)> {
+        self.nodes
+            .lock()
+            .unwrap()
+            .get(id)
+            .into_iter()
+            .flatten()
+            .map(|polled| (polled.node_id.clone(), polled.name.clone()))
+            .collect()
+    }
+
+    /// Create the updates for the values read, in the order of the snapshot.
+    ///
+    /// Values of nodes which were removed (or re-created) since the snapshot are dropped.
+    fn updates(
+        &self,
+        connection: &str,
+        id: &str,
+        subscription: &Subscription,
+        backfill: Option<&Backfill>,
+        read: Vec<(NodeId, String)>,
+        values: Vec<DataValue>,
+    ) -> Vec<Update> {
+        let mut updates = Vec::with_capacity(values.len());
+        let mut nodes = self.nodes.lock().unwrap();
+        let nodes = match nodes.get_mut(id) {
+            Some(nodes) => nodes,
+            None => return updates,
+        };
+
+        for ((node_id, name), value) in read.into_iter().zip(values) {
+            let polled = match nodes
+                .iter_mut()
+                .find(|polled| polled.node_id == node_id && polled.name == name)
+            {
+                Some(polled) => polled,
+                None => continue,
+            };
+
+            let current = (value.value.clone(), value.status);
+            if subscription.suppress_unchanged && polled.last.as_ref() == Some(&current) {
+                continue;
+            }
+            polled.last = Some(current);
+
+            if let Some(backfill) = backfill {
+                backfill.seen(id, &polled.node_id, &polled.name, &value);
+            }
+
+            updates.push(value_update(
+                connection,
+                id,
+                &polled.node_id,
+                &polled.name,
+                value,
+                polled.metadata.as_ref(),
+                &self.data_types,
+            ));
+        }
+
+        updates
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn value(value: i32) -> DataValue {
+        DataValue {
+            value: Some(Variant::Int32(value)),
+            status: Some(StatusCode::Good),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_remove_while_reading() {
+        let poller = Poller::new(DataTypes::default());
+        let subscription: Subscription = serde_json::from_value(json!({
+            "mode": "Poll",
+            "nodes": ["ns=1;s=Foo", "ns=1;s=Bar"],
+        }))
+        .unwrap();
+        poller.add(
+            "sub",
+            [
+                (NodeId::new(1, "Foo"), "foo".to_string(), None),
+                (NodeId::new(1, "Bar"), "bar".to_string(), None),
+            ],
+        );
+
+        let read = poller.snapshot("sub");
+
+        // re-created with a different ID, while the read is in flight
+        poller.remove("sub", "foo");
+        poller.add("sub", [(NodeId::new(1, "Baz"), "foo".to_string(), None)]);
+
+        let updates = poller.updates(
+            "conn",
+            "sub",
+            &subscription,
+            None,
+            read,
+            vec![value(1), value(2)],
+        );
+
+        assert_eq!(updates.len(), 1);
+        assert_eq!(
+            updates[0].extensions.get("nodeId"),
+            Some(&json!(NodeId::new(1, "Bar").to_string()))
+        );
+        assert_eq!(updates[0].address.last().map(String::as_str), Some("bar"));
+        assert_eq!(updates[0].value["value"], json!(2));
+    }
+}
@@ -0,0 +1,308 @@
+//! Resolving of configured node IDs, using namespace URIs or browse paths.
+
+use super::{opcua::client::prelude::*, services};
+use std::{
+    collections::HashMap,
+    str::FromStr,
+    sync::{Arc, Mutex},
+};
+
+/// The prefix of a node ID, using a namespace URI instead of an index.
+const NAMESPACE_URI_PREFIX: &str = "nsu=";
+
+/// The prefixes of the identifier types of a node ID.
+const IDENTIFIER_PREFIXES: &[&str] = &["i=", "s=", "g=", "b="];
+
+/// The separator of browse path elements, also the prefix of a browse path.
+const BROWSE_PATH_SEPARATOR: char = '/';
+
+/// Resolves node IDs, which depend on the server's address space.
+///
+/// Besides plain node IDs, those can be:
+///
+/// * Node IDs using a namespace URI (`nsu=<uri>;s=...`), resolved using the namespace array.
+/// * Browse paths, starting at the root folder (`/Objects/2:Line1/2:Pump3/2:Speed`), resolved
+///   using the TranslateBrowsePathsToNodeIds service. Each element is a browse name, optionally
+///   prefixed with its namespace index.
+#[derive(Clone, Default)]
+pub struct NodeIds {
+    /// The namespace array of the server, replaced as a whole when refreshed
+    namespaces: Arc<Mutex<Arc<Vec<String>>>>,
+    /// Resolved browse paths
+    paths: Arc<Mutex<HashMap<String, NodeId>>>,
+}
+
+impl NodeIds {
+    /// Read the namespace array, and resolve the browse paths, replacing the previous results.
+    ///
+    /// This must be called whenever a session is established, as the results may differ
+    /// between servers, or after a server restart. Returns the paths which now resolve to a
+    /// different node than before.
+    pub fn refresh<'a, I>(&self, session: &Session, paths: I) -> Vec<String>
+    where
+        I: IntoIterator<Item = &'a str>,
+    {
+        if let Err(status) = self.update_namespaces(session) {
+            log::warn!("Failed to read the namespace array: {status}");
+        }
+
+        // also refresh paths resolved on demand
+        let previous = std::mem::take(&mut *self.paths.lock().unwrap());
+        let mut paths = paths
+            .into_iter()
+            .filter(|path| is_browse_path(path))
+            .map(ToString::to_string)
+            .chain(previous.keys().cloned())
+            .collect::<Vec<_>>();
+        paths.sort_unstable();
+        paths.dedup();
+
+        if let Err(status) = self.resolve_paths(session, &paths) {
+            log::warn!("Failed to resolve browse paths: {status}");
+        }
+
+        let resolved = self.paths.lock().unwrap();
+        paths
+            .into_iter()
+            .filter(|path| resolved.get(path) != previous.get(path))
+            .collect()
+    }
+
+    /// Resolve a node ID, using the results of the last refresh.
+    ///
+    /// Browse paths which weren't resolved yet are resolved on demand.
+    pub fn resolve(&self, session: &Session, node_id: &str) -> Result<NodeId, StatusCode> {
+        if !is_browse_path(node_id) {
+            return parse(&self.namespaces.lock().unwrap(), node_id);
+        }
+
+        if let Some(resolved) = self.paths.lock().unwrap().get(node_id) {
+            return Ok(resolved.clone());
+        }
+
+        self.resolve_paths(session, &[node_id.to_string()])?;
+        self.paths
+            .lock()
+            .unwrap()
+            .get(node_id)
+            .cloned()
+            .ok_or(StatusCode::BadNoMatch)
+    }
+
+    /// The namespace array of the server, as read with the last refresh.
+    pub fn namespaces(&self) -> Arc<Vec<String>> {
+        self.namespaces.lock().unwrap().clone()
+    }
+
+    fn update_namespaces(&self, session: &Session) -> Result<(), StatusCode> {
+        let uris = match services::read_value(session, &VariableId::Server_NamespaceArray.into())? {
+            Some(Variant::Array(array)) => array
+                .values
+                .into_iter()
+                .map(|value| match value {
+                    Variant::String(uri) => uri.as_ref().to_string(),
+                    _ => String::new(),
+                })
+                .collect(),
+            _ => return Err(StatusCode::BadTypeMismatch),
+        };
+
+        *self.namespaces.lock().unwrap() = Arc::new(uris);
+
+        Ok(())
+    }
+
+    /// Resolve browse paths with a single call, storing the ones which could be resolved.
+    fn resolve_paths(&self, session: &Session, paths: &[String]) -> Result<(), StatusCode> {
+        let mut parsed = Vec::with_capacity(paths.len());
+        let mut browse_paths = Vec::with_capacity(paths.len());
+        for path in paths {
+            match browse_path(path) {
+                Ok(browse_path) => {
+                    parsed.push(path);
+                    browse_paths.push(browse_path);
+                }
+                Err(status) => log::info!("Invalid browse path '{path}': {status}"),
+            }
+        }
+
+        if browse_paths.is_empty() {
+            return Ok(());
+        }
+
+        let results = session.translate_browse_paths_to_node_ids(&browse_paths)?;
+
+        // the result has the same order as the request list
+
+        let mut resolved = self.paths.lock().unwrap();
+        for (path, result) in parsed.into_iter().zip(results) {
+            let target = result
+                .targets
+                .filter(|_| result.status_code.is_good())
+                .and_then(|targets| targets.into_iter().next());
+
+            match target {
+                Some(target) => {
+                    log::debug!("Resolved '{path}' to {}", target.target_id.node_id);
+                    resolved.insert(path.clone(), target.target_id.node_id);
+                }
+                None => log::info!("Unable to resolve '{path}': {}", result.status_code),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Check if a configured node ID is a browse path.
+pub fn is_browse_path(node_id: &str) -> bool {
+    node_id.starts_with(BROWSE_PATH_SEPARATOR)
+}
+
+/// Parse a node ID, resolving a namespace URI to its index in the namespace array.
+fn parse(namespaces: &[String], node_id: &str) -> Result<NodeId, StatusCode> {
+    let (uri, identifier) = match node_id
+        .strip_prefix(NAMESPACE_URI_PREFIX)
+        .and_then(split_namespace_uri)
+    {
+        Some(split) => split,
+        None => return NodeId::from_str(node_id),
+    };
+
+    let index = namespaces
+        .iter()
+        .position(|candidate| candidate == uri)
+        .ok_or(StatusCode::BadNodeIdUnknown)?;
+
+    NodeId::from_str(&format!("ns={index};{identifier}"))
+}
+
+/// Split the namespace URI from the identifier.
+///
+/// URIs may contain `;` as well, so this splits at the last one followed by an identifier.
+fn split_namespace_uri(value: &str) -> Option<(&str, &str)> {
+    let (index, _) = value.rmatch_indices(';').find(|(index, _)| {
+        let identifier = &value[index + 1..];
+        IDENTIFIER_PREFIXES
+            .iter()
+            .any(|prefix| identifier.starts_with(prefix))
+    })?;
+    Some((&value[..index], &value[index + 1..]))
+}
+
+/// Parse a browse path, starting at the root folder.
+fn browse_path(path: &str) -> Result<BrowsePath, StatusCode> {
+    let elements = path
+        .strip_prefix(BROWSE_PATH_SEPARATOR)
+        .ok_or(StatusCode::BadBrowseNameInvalid)?
+        .split(BROWSE_PATH_SEPARATOR)
+        .map(|name| {
+            Ok(RelativePathElement {
+                reference_type_id: ReferenceTypeId::HierarchicalReferences.into(),
+                is_inverse: false,
+                include_subtypes: true,
+                target_name: browse_name(name)?,
+            })
+        })
+        .collect::<Result<Vec<_>, StatusCode>>()?;
+
+    Ok(BrowsePath {
+        starting_node: ObjectId::RootFolder.into(),
+        relative_path: RelativePath {
+            elements: Some(elements),
+        },
+    })
+}
+
+/// Parse a browse name, with an optional namespace index (`2:Pump3`).
+fn browse_name(name: &str) -> Result<QualifiedName, StatusCode> {
+    let (namespace, name) = match name.split_once(':') {
+        Some((namespace, name)) if namespace.chars().all(|c| c.is_ascii_digit()) => (
+            namespace
+                .parse()
+                .map_err(|_| StatusCode::BadBrowseNameInvalid)?,
+            name,
+        ),
+        _ => (0, name),
+    };
+
+    if name.is_empty() {
+        return Err(StatusCode::BadBrowseNameInvalid);
+    }
+
+    Ok(QualifiedName::new(namespace, name))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let namespaces = [
+            "http://opcfoundation.org/UA/".to_string(),
+            "urn:server".to_string(),
+            "urn:machine".to_string(),
+        ];
+
+        assert_eq!(
+            parse(&namespaces, "nsu=urn:machine;s=Foo"),
+            Ok(NodeId::new(2, "Foo"))
+        );
+        assert_eq!(
+            parse(&namespaces, "nsu=urn:server;i=42"),
+            Ok(NodeId::new(1, 42u32))
+        );
+        assert_eq!(parse(&namespaces, "ns=1;s=Foo"), Ok(NodeId::new(1, "Foo")));
+        assert_eq!(
+            parse(&namespaces, "nsu=urn:unknown;s=Foo"),
+            Err(StatusCode::BadNodeIdUnknown)
+        );
+    }
+
+    #[test]
+    fn test_parse_uri_separator() {
+        let namespaces = [
+            "http://opcfoundation.org/UA/".to_string(),
+            "urn:server;version=2".to_string(),
+        ];
+
+        assert_eq!(
+            parse(&namespaces, "nsu=urn:server;version=2;s=Foo"),
+            Ok(NodeId::new(1, "Foo"))
+        );
+        assert_eq!(
+            parse(&namespaces, "nsu=urn:server;version=2;i=42"),
+            Ok(NodeId::new(1, 42u32))
+        );
+        assert_eq!(
+            split_namespace_uri("urn:server;version=2;s=Foo"),
+            Some(("urn:server;version=2", "s=Foo"))
+        );
+        assert_eq!(split_namespace_uri("urn:server;version=2"), None);
+    }
+
+    #[test]
+    fn test_browse_path() {
+        let path = browse_path("/Objects/2:Line1/2:Pump3/2:Speed").unwrap();
+        assert_eq!(path.starting_node, NodeId::from(ObjectId::RootFolder));
+        assert_eq!(
+            path.relative_path
+                .elements
+                .unwrap_or_default()
+                .into_iter()
+                .map(|element| element.target_name)
+                .collect::<Vec<_>>(),
+            vec![
+                QualifiedName::new(0, "Objects"),
+                QualifiedName::new(2, "Line1"),
+                QualifiedName::new(2, "Pump3"),
+                QualifiedName::new(2, "Speed"),
+            ]
+        );
+
+        assert!(browse_path("Objects/2:Line1").is_err());
+        assert!(browse_path("/Objects//2:Line1").is_err());
+        assert_eq!(browse_name("Foo:Bar"), Ok(QualifiedName::new(0, "Foo:Bar")));
+    }
+}
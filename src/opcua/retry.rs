@@ -0,0 +1,277 @@
+//! Retrying of monitored items, which failed to be created.
+
+use super::{
+    data_change_filter, monitored_item, opcua::client::prelude::*, relink_triggering,
+    resolve::NodeIds, services, structures::DataTypes, subscribed, EventSender, MonitoringSettings,
+    Node, RwLock, Subscribed, Subscription, Timestamps,
+};
+use crate::middleware::Update;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// A node, which failed to be monitored.
+pub struct Failed {
+    pub subscription_id: u32,
+    /// The node ID, unless it could not be resolved yet
+    pub node_id: Option<NodeId>,
+    /// The configured node ID, resolved when retrying
+    pub reference: String,
+    pub name: String,
+    /// The effective monitoring settings of the node.
+    pub monitoring: MonitoringSettings,
+}
+
+impl Failed {
+    pub fn new(
+        subscription_id: u32,
+        node: &Node,
+        node_id: Option<NodeId>,
+        monitoring: MonitoringSettings,
+    ) -> Self {
+        Self {
+            subscription_id,
+            node_id,
+            reference: node.id.clone(),
+            name: node.name(),
+            monitoring,
+        }
+    }
+}
+
+/// The failed monitored items of subscriptions.
+#[derive(Clone)]
+pub struct Retry {
+    /// Failed nodes, by subscription
+    failed: Arc<Mutex<HashMap<String, Vec<Failed>>>>,
+    /// Set when the session terminated
+    stopped: Arc<AtomicBool>,
+    /// Resolves node IDs, which could not be resolved before
+    node_ids: NodeIds,
+    /// Data types of the server, loaded for nodes once resolved
+    data_types: DataTypes,
+}
+
+impl Retry {
+    pub fn new(node_ids: NodeIds, data_types: DataTypes) -> Self {
+        Self {
+            failed: Default::default(),
+            stopped: Default::default(),
+            node_ids,
+            data_types,
+        }
+    }
+
+    /// Add failed nodes of a subscription, picked up with the next retry.
+    pub fn add<I>(&self, subscription: &str, failed: I)
+    where
+        I: IntoIterator<Item = Failed>,
+    {
+        self.failed
+            .lock()
+            .unwrap()
+            .entry(subscription.to_string())
+            .or_default()
+            .extend(failed);
+    }
+
+    /// Remove a failed node of a subscription, by name.
+    pub fn remove(&self, subscription: &str, name: &str) {
+        if let Some(failed) = self.failed.lock().unwrap().get_mut(subscription) {
+            failed.retain(|node| node.name != name);
+        }
+    }
+
+    /// Stop retrying, as the session terminated.
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::SeqCst);
+    }
+
+    /// Retry the failed nodes of a subscription, until stopped.
+    ///
+    /// Nodes which could be monitored are reported as subscribed, and get their triggering links
+    /// again. This blocks the current thread.
+    pub fn run(
+        &self,
+        connection: &str,
+        session: Arc<RwLock<Session>>,
+        id: &str,
+        subscription: &Subscription,
+        created: Subscribed,
+        mut tx: EventSender,
+    ) {
+        let interval = match subscription.retry_interval {
+            Some(interval) => interval,
+            None => return,
+        };
+
+        loop {
+            std::thread::sleep(interval);
+
+            if self.stopped.load(Ordering::SeqCst) || tx.0.is_closed() {
+                break;
+            }
+
+            let failed = match self.failed.lock().unwrap().get_mut(id) {
+                Some(failed) if !failed.is_empty() => std::mem::take(failed),
+                _ => continue,
+            };
+
+            let (updates, failed) = {
+                #[cfg(feature = "opcua_0_11")]
+                let session = session.read();
+                #[cfg(not(feature = "opcua_0_11"))]
+                let session = session.read().unwrap();
+
+                if session.is_connected() {
+                    self.retry(&session, connection, id, subscription, &created, failed)
+                } else {
+                    (vec![], failed)
+                }
+            };
+
+            // keep the ones still failing, for the next attempt
+            self.add(id, failed);
+
+            if !updates.is_empty() {
+                tx.update_blocking(updates);
+            }
+        }
+
+        log::debug!("Stopped retrying subscription {id}");
+    }
+
+    /// Try to create monitored items for the failed nodes.
+    ///
+    /// Returns the updates to send, and the nodes which still failed.
+    fn retry(
+        &self,
+        session: &Session,
+        connection: &str,
+        id: &str,
+        subscription: &Subscription,
+        created: &Subscribed,
+        failed: Vec<Failed>,
+    ) -> (Vec<Update>, Vec<Failed>) {
+        let mut updates = Vec::new();
+        let mut still_failed = Vec::new();
+        let mut recovered = HashSet::new();
+
+        let mut groups =
+            HashMap::<(u32, Timestamps), Vec<(Failed, MonitoredItemCreateRequest)>>::new();
+        for mut node in failed {
+            let node_id = match self.resolve(session, subscription, created, &mut node) {
+                Ok(node_id) => node_id,
+                Err(_) => {
+                    still_failed.push(node);
+                    continue;
+                }
+            };
+
+            match data_change_filter(session, &node_id, &node.monitoring) {
+                Ok(filter) => {
+                    let request = monitored_item(node_id, &node.monitoring, filter);
+                    groups
+                        .entry((
+                            node.subscription_id,
+                            node.monitoring.timestamps.unwrap_or_default(),
+                        ))
+                        .or_default()
+                        .push((node, request));
+                }
+                Err(_) => still_failed.push(node),
+            }
+        }
+
+        // in batches, as servers limit the monitored items per call
+        let limit = services::operation_limit(
+            session,
+            VariableId::Server_ServerCapabilities_OperationLimits_MaxMonitoredItemsPerCall,
+        );
+        let batches = groups.into_iter().flat_map(|(key, nodes)| {
+            services::batches(nodes, limit)
+                .into_iter()
+                .map(move |nodes| (key, nodes))
+        });
+
+        for ((subscription_id, timestamps), nodes) in batches {
+            let (nodes, items_to_create): (Vec<_>, Vec<_>) = nodes.into_iter().unzip();
+
+            let result = match session.create_monitored_items(
+                subscription_id,
+                timestamps.into(),
+                &items_to_create,
+            ) {
+                Ok(result) => result,
+                Err(StatusCode::BadSubscriptionIdInvalid) => {
+                    // the subscription is gone, and its nodes with it
+                    log::info!("Dropping failed nodes of subscription {id}, it no longer exists");
+                    continue;
+                }
+                Err(status) => {
+                    log::info!("Failed to retry nodes of subscription {id}: {status}");
+                    still_failed.extend(nodes);
+                    continue;
+                }
+            };
+
+            // the result has the same order as the request list
+
+            for (node, res) in nodes.into_iter().zip(result.into_iter()) {
+                if res.status_code.is_good() {
+                    log::info!("Subscribed to {} after retrying", node.name);
+                    updates.push(subscribed(connection, id, &node.name));
+                    created
+                        .monitored_items
+                        .lock()
+                        .unwrap()
+                        .insert(node.name.clone(), res.monitored_item_id);
+                    recovered.insert(node.name);
+                } else {
+                    log::debug!("Retrying {} failed: {}", node.name, res.status_code);
+                    still_failed.push(node);
+                }
+            }
+        }
+
+        if !recovered.is_empty() {
+            let monitored_items = created.monitored_items.lock().unwrap().clone();
+            relink_triggering(
+                session,
+                created.subscription_id,
+                &subscription.nodes,
+                &monitored_items,
+                &recovered,
+            );
+        }
+
+        (updates, still_failed)
+    }
+
+    /// Resolve the ID of a failed node, registering it with the subscription once resolved.
+    fn resolve(
+        &self,
+        session: &Session,
+        subscription: &Subscription,
+        created: &Subscribed,
+        node: &mut Failed,
+    ) -> Result<NodeId, StatusCode> {
+        if let Some(node_id) = &node.node_id {
+            return Ok(node_id.clone());
+        }
+
+        let node_id = self.node_ids.resolve(session, &node.reference)?;
+        log::debug!("Resolved {} to {node_id} after retrying", node.reference);
+
+        created.add_node(session, &node_id, &node.name, subscription.metadata);
+        self.data_types
+            .load_variables(session, std::slice::from_ref(&node_id));
+        node.node_id = Some(node_id.clone());
+
+        Ok(node_id)
+    }
+}
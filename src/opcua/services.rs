@@ -0,0 +1,98 @@
+//! Helpers for calling OPC UA services on a session.
+
+use super::opcua::client::prelude::*;
+
+/// Resolve a property of a node, using the browse name of the property.
+pub fn find_property(
+    session: &Session,
+    node_id: &NodeId,
+    name: QualifiedName,
+) -> Result<Option<NodeId>, StatusCode> {
+    let path = BrowsePath {
+        starting_node: node_id.clone(),
+        relative_path: RelativePath {
+            elements: Some(vec![RelativePathElement {
+                reference_type_id: ReferenceTypeId::HasProperty.into(),
+                is_inverse: false,
+                include_subtypes: true,
+                target_name: name,
+            }]),
+        },
+    };
+
+    let target = session
+        .translate_browse_paths_to_node_ids(&[path])?
+        .into_iter()
+        .next()
+        .filter(|result| result.status_code.is_good())
+        .and_then(|result| result.targets)
+        .and_then(|targets| targets.into_iter().next());
+
+    Ok(target.map(|target| target.target_id.node_id))
+}
+
+/// Read the value of a single node.
+pub fn read_value(session: &Session, node_id: &NodeId) -> Result<Option<Variant>, StatusCode> {
+    let value = session
+        .read(&[node_id.clone().into()], TimestampsToReturn::Neither, 0.0)?
+        .into_iter()
+        .next();
+
+    match value {
+        Some(DataValue {
+            status: Some(status),
+            ..
+        }) if !status.is_good() => Err(status),
+        Some(value) => Ok(value.value),
+        None => Ok(None),
+    }
+}
+
+/// Read an operation limit of the server, returning `None` if there is no (known) limit.
+pub fn operation_limit(session: &Session, limit: VariableId) -> Option<usize> {
+    match read_value(session, &limit.into()) {
+        Ok(Some(Variant::UInt32(limit))) if limit > 0 => Some(limit as usize),
+        _ => None,
+    }
+}
+
+/// Split the items of a request into batches, so that each stays within an operation limit.
+pub fn batches<T>(items: Vec<T>, limit: Option<usize>) -> Vec<Vec<T>> {
+    let limit = limit.unwrap_or(usize::MAX);
+    let mut items = items.into_iter().peekable();
+    let mut batches = vec![];
+    while items.peek().is_some() {
+        batches.push(items.by_ref().take(limit).collect());
+    }
+    batches
+}
+
+/// Read the EURange property of a node, if it has one.
+pub fn read_eu_range(session: &Session, node_id: &NodeId) -> Result<Option<Range>, StatusCode> {
+    let property = match find_property(session, node_id, QualifiedName::new(0, "EURange"))? {
+        Some(property) => property,
+        None => return Ok(None),
+    };
+
+    match read_value(session, &property)? {
+        Some(Variant::ExtensionObject(value)) => value
+            .decode_inner::<Range>(&DecodingOptions::default())
+            .map(Some),
+        _ => Ok(None),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_batches() {
+        assert_eq!(
+            batches(vec![1, 2, 3, 4, 5], Some(2)),
+            vec![vec![1, 2], vec![3, 4], vec![5]]
+        );
+        assert_eq!(batches(vec![1, 2, 3], None), vec![vec![1, 2, 3]]);
+        assert!(batches(Vec::<u32>::new(), Some(2)).is_empty());
+    }
+}
@@ -0,0 +1,1247 @@
+//! Decoding and encoding of structured values, using the type system of the server.
+//!
+//! Structures are loaded using the `DataTypeDefinition` attribute of their data type, or the
+//! legacy type dictionary of the server, if the data type has no definition.
+
+use super::{
+    coerce::{self, TypeInfo},
+    dictionary::{self, Dictionary},
+    json,
+    opcua::client::prelude::*,
+    resolve::NodeIds,
+    services, IntoVariant, JsonEncoding,
+};
+use crate::ToJson;
+use serde_json::{Map, Value};
+use std::{
+    collections::HashMap,
+    io::{Cursor, Read, Write},
+    sync::{Arc, Mutex},
+};
+
+/// The built-in type ID of `ExtensionObject`, also the data type ID of `Structure`.
+const EXTENSION_OBJECT: u32 = 22;
+/// The built-in type ID of `Variant`, also the data type ID of `BaseDataType`.
+const VARIANT: u32 = 24;
+/// The highest built-in type ID.
+const MAX_BUILT_IN_TYPE: u32 = 25;
+/// The maximum nesting of structures.
+const MAX_DEPTH: usize = 32;
+
+/// The kind of a data type, defining how its values are encoded.
+#[derive(Clone, Debug, PartialEq)]
+enum Kind {
+    /// A built-in type, or a sub-type of it, by its built-in type ID.
+    BuiltIn(u32),
+    /// An enumeration, encoded as `Int32`.
+    Enumeration,
+    /// A structure, by its data type ID.
+    Structure(NodeId),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Field {
+    name: String,
+    kind: Kind,
+    value_rank: i32,
+    is_optional: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct Structure {
+    structure_type: StructureType,
+    fields: Vec<Field>,
+}
+
+#[derive(Default)]
+struct Registry {
+    /// Resolved data types, by data type ID
+    kinds: HashMap<NodeId, Kind>,
+    /// Structures, by data type ID
+    structures: HashMap<NodeId, Structure>,
+    /// Data type IDs, by their binary encoding ID
+    encodings: HashMap<NodeId, NodeId>,
+    /// Legacy type dictionaries, by their node ID
+    dictionaries: HashMap<NodeId, Arc<Dictionary>>,
+}
+
+/// The data types of the server, for decoding and encoding structures.
+///
+/// This also converts values to JSON, using the configured encoding.
+#[derive(Clone, Default)]
+pub struct DataTypes {
+    /// Whether data types are loaded from the server
+    enabled: bool,
+    registry: Arc<Mutex<Registry>>,
+    encoding: JsonEncoding,
+    /// For the namespace array of the server
+    node_ids: NodeIds,
+}
+
+enum Definition {
+    Structure(StructureDefinition),
+    Enumeration,
+}
+
+impl Kind {
+    /// The variant type of values of this kind, if they can be coerced from JSON.
+    fn variant_type(&self) -> Option<VariantTypeId> {
+        match self {
+            Kind::BuiltIn(id) => variant_type(*id),
+            Kind::Enumeration => Some(VariantTypeId::Int32),
+            Kind::Structure(_) => None,
+        }
+    }
+}
+
+impl DataTypes {
+    pub fn new(enabled: bool, encoding: JsonEncoding, node_ids: NodeIds) -> Self {
+        Self {
+            enabled,
+            registry: Default::default(),
+            encoding,
+            node_ids,
+        }
+    }
+
+    /// Forget the loaded data types, as they may differ for the next session.
+    pub fn clear(&self) {
+        *self.registry.lock().unwrap() = Default::default();
+    }
+
+    /// Load the data types of variables, so that their values can be decoded.
+    ///
+    /// Failures are logged, values of those variables will be kept encoded.
+    pub fn load_variables(&self, session: &Session, node_ids: &[NodeId]) {
+        if !self.enabled || node_ids.is_empty() {
+            return;
+        }
+
+        let nodes_to_read = node_ids
+            .iter()
+            .map(|node_id| ReadValueId {
+                node_id: node_id.clone(),
+                attribute_id: AttributeId::DataType as u32,
+                index_range: UAString::null(),
+                data_encoding: QualifiedName::null(),
+            })
+            .collect::<Vec<_>>();
+
+        let values = match session.read(&nodes_to_read, TimestampsToReturn::Neither, 0.0) {
+            Ok(values) => values,
+            Err(status) => {
+                log::info!("Failed to read the data types of variables: {status}");
+                return;
+            }
+        };
+
+        for value in values {
+            if let Some(Variant::NodeId(data_type)) = value.value {
+                if let Err(status) = self.kind(session, &data_type) {
+                    log::info!("Failed to load data type {data_type}: {status}");
+                }
+            }
+        }
+    }
+
+    /// Load the data types of the output arguments of a method, so that results can be decoded.
+    ///
+    /// Failures are logged, those results will be kept encoded.
+    pub fn load_output_arguments(&self, session: &Session, method_id: &NodeId) {
+        if !self.enabled {
+            return;
+        }
+
+        let arguments = match output_arguments(session, method_id) {
+            Ok(arguments) => arguments,
+            Err(status) => {
+                log::info!("Failed to read the output arguments of {method_id}: {status}");
+                return;
+            }
+        };
+
+        for argument in arguments {
+            if let Err(status) = self.kind(session, &argument.data_type) {
+                log::info!("Failed to load data type {}: {status}", argument.data_type);
+            }
+        }
+    }
+
+    /// Convert a value to JSON, decoding known structures.
+    pub fn to_json(&self, value: DataValue) -> Value {
+        if let Some(json) = self.encode_json(|encoder| encoder.data_value(&value)) {
+            return json;
+        }
+
+        let decoded = value.value.as_ref().and_then(|value| self.decode(value));
+        let mut json = value.to_json();
+        if let (Some(decoded), Value::Object(json)) = (decoded, &mut json) {
+            json.insert("value".to_string(), decoded);
+        }
+        json
+    }
+
+    /// Convert a variant to JSON, decoding known structures.
+    pub fn variant_to_json(&self, value: Variant) -> Value {
+        if let Some(json) = self.encode_json(|encoder| encoder.variant(&value)) {
+            return json;
+        }
+
+        self.decode(&value).unwrap_or_else(|| value.to_json())
+    }
+
+    /// Encode a value using the OPC UA JSON encoding, if configured.
+    fn encode_json<F>(&self, f: F) -> Option<Value>
+    where
+        F: FnOnce(&json::Encoder) -> Value,
+    {
+        let reversible = match self.encoding {
+            JsonEncoding::Simple => return None,
+            JsonEncoding::Reversible => true,
+            JsonEncoding::NonReversible => false,
+        };
+
+        let namespaces = self.node_ids.namespaces();
+
+        Some(f(&json::Encoder {
+            reversible,
+            namespaces: &namespaces,
+            data_types: self,
+        }))
+    }
+
+    /// Decode an extension object, if it is a known structure.
+    pub fn decode_object(&self, object: &ExtensionObject) -> Option<Value> {
+        self.registry.lock().unwrap().decode_object(object, 0).ok()
+    }
+
+    /// Decode a value holding known structures, returning `None` if there are none.
+    pub fn decode(&self, value: &Variant) -> Option<Value> {
+        let registry = self.registry.lock().unwrap();
+        if registry.encodings.is_empty() {
+            return None;
+        }
+
+        match value {
+            Variant::ExtensionObject(object) => registry.decode_object(object, 0).ok(),
+            Variant::Array(array)
+                if array
+                    .values
+                    .iter()
+                    .any(|value| matches!(value, Variant::ExtensionObject(_))) =>
+            {
+                Some(
+                    array
+                        .values
+                        .iter()
+                        .map(|value| match value {
+                            Variant::ExtensionObject(object) => registry
+                                .decode_object(object, 0)
+                                .unwrap_or_else(|_| value.clone().to_json()),
+                            value => value.clone().to_json(),
+                        })
+                        .collect(),
+                )
+            }
+            _ => None,
+        }
+    }
+
+    /// Encode a JSON value into a structure, if the data type is a structure.
+    ///
+    /// Returns `None` if the data type is not a (known) structure.
+    pub fn encode(
+        &self,
+        session: &Session,
+        type_info: &TypeInfo,
+        value: &Value,
+    ) -> Option<Result<Variant, StatusCode>> {
+        if type_info.variant_type.is_some() {
+            return None;
+        }
+
+        let data_type = type_info.data_type.as_ref()?;
+        if !matches!(self.kind(session, data_type), Ok(Kind::Structure(_))) {
+            return None;
+        }
+
+        let registry = self.registry.lock().unwrap();
+        Some(match value {
+            Value::Array(values) if type_info.value_rank != -1 => values
+                .iter()
+                .map(|value| {
+                    registry
+                        .encode_object(data_type, value)
+                        .map(|object| Variant::ExtensionObject(Box::new(object)))
+                })
+                .collect::<Result<Vec<_>, _>>()
+                .and_then(|values| {
+                    Array::new_single(VariantTypeId::ExtensionObject, values)
+                        .map(|array| Variant::Array(Box::new(array)))
+                        .map_err(|_| StatusCode::BadTypeMismatch)
+                }),
+            value => registry
+                .encode_object(data_type, value)
+                .map(|object| Variant::ExtensionObject(Box::new(object))),
+        })
+    }
+
+    /// Get the variant type of a data type, following its super types up to a built-in type.
+    ///
+    /// Returns `None` for structures, and data types which can't be resolved.
+    pub fn variant_type(&self, session: &Session, data_type: &NodeId) -> Option<VariantTypeId> {
+        match self.kind(session, data_type) {
+            Ok(kind) => kind.variant_type(),
+            Err(status) => {
+                log::info!("Failed to resolve data type {data_type}: {status}");
+                None
+            }
+        }
+    }
+
+    /// Resolve the kind of a data type, loading it from the server if required.
+    ///
+    /// Unless enabled, structures aren't loaded, and resolve to their built-in super type.
+    fn kind(&self, session: &Session, data_type: &NodeId) -> Result<Kind, StatusCode> {
+        if let Some(kind) = built_in(data_type) {
+            return Ok(kind);
+        }
+        if let Some(kind) = self.registry.lock().unwrap().kinds.get(data_type) {
+            return Ok(kind.clone());
+        }
+
+        let definition = if self.enabled {
+            read_definition(session, data_type)?
+        } else {
+            None
+        };
+
+        let kind = match definition {
+            Some(Definition::Structure(definition)) => self.register(data_type, || {
+                self.load_structure(session, data_type, definition)
+            })?,
+            Some(Definition::Enumeration) => Kind::Enumeration,
+            None => {
+                // encoded the same way as its super type
+                let kind = self.kind(session, &super_type(session, data_type)?)?;
+
+                // unless it is a structure, described by the type dictionary only
+                let structure =
+                    matches!(kind, Kind::BuiltIn(EXTENSION_OBJECT) | Kind::Structure(_));
+                if self.enabled && structure {
+                    self.register(data_type, || self.load_legacy_structure(session, data_type))
+                        .unwrap_or_else(|status| {
+                            log::debug!("No type dictionary for structure {data_type}: {status}");
+                            kind
+                        })
+                } else {
+                    kind
+                }
+            }
+        };
+
+        self.registry
+            .lock()
+            .unwrap()
+            .kinds
+            .insert(data_type.clone(), kind.clone());
+
+        Ok(kind)
+    }
+
+    /// Register a structure, loaded by the function, along with its binary encoding ID.
+    fn register<F>(&self, data_type: &NodeId, load: F) -> Result<Kind, StatusCode>
+    where
+        F: FnOnce() -> Result<(NodeId, Structure), StatusCode>,
+    {
+        let kind = Kind::Structure(data_type.clone());
+        // registered upfront, for structures referencing themselves
+        self.registry
+            .lock()
+            .unwrap()
+            .kinds
+            .insert(data_type.clone(), kind.clone());
+
+        let (encoding_id, structure) = match load() {
+            Ok(loaded) => loaded,
+            Err(status) => {
+                self.registry.lock().unwrap().kinds.remove(data_type);
+                return Err(status);
+            }
+        };
+
+        log::debug!("Loaded structure {data_type}, encoded as {encoding_id}");
+
+        let mut registry = self.registry.lock().unwrap();
+        registry.encodings.insert(encoding_id, data_type.clone());
+        registry.structures.insert(data_type.clone(), structure);
+
+        Ok(kind)
+    }
+
+    fn load_structure(
+        &self,
+        session: &Session,
+        data_type: &NodeId,
+        definition: StructureDefinition,
+    ) -> Result<(NodeId, Structure), StatusCode> {
+        let encoding_id = if definition.default_encoding_id.is_null() {
+            default_binary_encoding(session, data_type)?
+        } else {
+            definition.default_encoding_id
+        };
+
+        let fields = definition
+            .fields
+            .unwrap_or_default()
+            .into_iter()
+            .map(|field| {
+                Ok(Field {
+                    name: field.name.as_ref().to_string(),
+                    kind: self.kind(session, &field.data_type)?,
+                    value_rank: field.value_rank,
+                    is_optional: field.is_optional,
+                })
+            })
+            .collect::<Result<Vec<_>, StatusCode>>()?;
+
+        Ok((
+            encoding_id,
+            Structure {
+                structure_type: definition.structure_type,
+                fields,
+            },
+        ))
+    }
+
+    /// Load a structure from the type dictionary, which describes its binary encoding.
+    ///
+    /// The encoding refers to its description in the dictionary, which has the name of the
+    /// structure as value.
+    fn load_legacy_structure(
+        &self,
+        session: &Session,
+        data_type: &NodeId,
+    ) -> Result<(NodeId, Structure), StatusCode> {
+        let encoding_id = default_binary_encoding(session, data_type)?;
+        let description = browse_first(
+            session,
+            &encoding_id,
+            BrowseDirection::Forward,
+            ReferenceTypeId::HasDescription,
+            |_| true,
+        )?;
+        let name = match services::read_value(session, &description)? {
+            Some(Variant::String(name)) => name.as_ref().to_string(),
+            _ => return Err(StatusCode::BadDataTypeIdUnknown),
+        };
+        let dictionary_id = browse_first(
+            session,
+            &description,
+            BrowseDirection::Inverse,
+            ReferenceTypeId::HasComponent,
+            |_| true,
+        )?;
+        let dictionary = self.dictionary(session, &dictionary_id)?;
+
+        let (structure_type, fields) = match dictionary.get(&name) {
+            Some(dictionary::Description::Structure {
+                structure_type,
+                fields,
+            }) => (*structure_type, fields),
+            _ => return Err(StatusCode::BadDataTypeIdUnknown),
+        };
+
+        let fields = fields
+            .iter()
+            .map(|field| {
+                let kind = match &field.type_name {
+                    dictionary::TypeName::BuiltIn(id) => Kind::BuiltIn(*id),
+                    dictionary::TypeName::Local(name) => match dictionary.get(name) {
+                        Some(dictionary::Description::Enumeration) => Kind::Enumeration,
+                        Some(dictionary::Description::Structure { .. }) => {
+                            let data_type = legacy_data_type(session, &dictionary_id, name)?;
+                            // nested structures are encoded inline, so they must be known
+                            match self.kind(session, &data_type)? {
+                                kind @ Kind::Structure(_) => kind,
+                                _ => return Err(StatusCode::BadDataTypeIdUnknown),
+                            }
+                        }
+                        None => return Err(StatusCode::BadDataTypeIdUnknown),
+                    },
+                };
+
+                Ok(Field {
+                    name: field.name.clone(),
+                    kind,
+                    value_rank: if field.is_array { 1 } else { -1 },
+                    is_optional: field.is_optional,
+                })
+            })
+            .collect::<Result<Vec<_>, StatusCode>>()?;
+
+        Ok((
+            encoding_id,
+            Structure {
+                structure_type,
+                fields,
+            },
+        ))
+    }
+
+    /// Get a type dictionary, reading and parsing it once.
+    fn dictionary(
+        &self,
+        session: &Session,
+        dictionary_id: &NodeId,
+    ) -> Result<Arc<Dictionary>, StatusCode> {
+        if let Some(dictionary) = self
+            .registry
+            .lock()
+            .unwrap()
+            .dictionaries
+            .get(dictionary_id)
+        {
+            return Ok(dictionary.clone());
+        }
+
+        let xml = match services::read_value(session, dictionary_id)? {
+            Some(Variant::ByteString(xml)) => xml.value.unwrap_or_default(),
+            _ => return Err(StatusCode::BadTypeMismatch),
+        };
+        let xml = String::from_utf8(xml).map_err(|_| StatusCode::BadDecodingError)?;
+        let dictionary = Arc::new(dictionary::parse(&xml)?);
+
+        self.registry
+            .lock()
+            .unwrap()
+            .dictionaries
+            .insert(dictionary_id.clone(), dictionary.clone());
+
+        Ok(dictionary)
+    }
+}
+
+impl Registry {
+    fn decode_object(&self, object: &ExtensionObject, depth: usize) -> Result<Value, StatusCode> {
+        let data_type = self
+            .encodings
+            .get(&object.node_id)
+            .ok_or(StatusCode::BadDataTypeIdUnknown)?;
+
+        let body = match &object.body {
+            ExtensionObjectEncoding::ByteString(body) => body.value.as_deref().unwrap_or_default(),
+            _ => return Err(StatusCode::BadDataEncodingUnsupported),
+        };
+
+        self.decode_structure(&mut Cursor::new(body), data_type, depth)
+    }
+
+    fn decode_structure<S: Read>(
+        &self,
+        stream: &mut S,
+        data_type: &NodeId,
+        depth: usize,
+    ) -> Result<Value, StatusCode> {
+        if depth > MAX_DEPTH {
+            return Err(StatusCode::BadEncodingLimitsExceeded);
+        }
+
+        let structure = self
+            .structures
+            .get(data_type)
+            .ok_or(StatusCode::BadDataTypeIdUnknown)?;
+        let options = DecodingOptions::default();
+
+        let mut m = Map::new();
+        match structure.structure_type {
+            StructureType::Structure => {
+                for field in &structure.fields {
+                    m.insert(field.name.clone(), self.decode_field(stream, field, depth)?);
+                }
+            }
+            StructureType::StructureWithOptionalFields => {
+                let mask = u32::decode(stream, &options)?;
+                let mut optional = 0;
+                for field in &structure.fields {
+                    if field.is_optional {
+                        // the mask has a bit per optional field
+                        if optional >= 32 {
+                            return Err(StatusCode::BadDecodingError);
+                        }
+                        let present = mask & (1 << optional) != 0;
+                        optional += 1;
+                        if !present {
+                            continue;
+                        }
+                    }
+                    m.insert(field.name.clone(), self.decode_field(stream, field, depth)?);
+                }
+            }
+            StructureType::Union => {
+                let switch = u32::decode(stream, &options)? as usize;
+                if switch > 0 {
+                    let field = structure
+                        .fields
+                        .get(switch - 1)
+                        .ok_or(StatusCode::BadDecodingError)?;
+                    m.insert(field.name.clone(), self.decode_field(stream, field, depth)?);
+                }
+            }
+        }
+
+        Ok(Value::Object(m))
+    }
+
+    fn decode_field<S: Read>(
+        &self,
+        stream: &mut S,
+        field: &Field,
+        depth: usize,
+    ) -> Result<Value, StatusCode> {
+        match field.value_rank {
+            -1 => self.decode_value(stream, &field.kind, depth),
+            1 => {
+                let options = DecodingOptions::default();
+                let len = i32::decode(stream, &options)?;
+                if len == -1 {
+                    return Ok(Value::Null);
+                }
+                if len < -1 || len as usize > options.max_array_length {
+                    return Err(StatusCode::BadDecodingError);
+                }
+
+                (0..len)
+                    .map(|_| self.decode_value(stream, &field.kind, depth))
+                    .collect()
+            }
+            // multi-dimensional arrays are not supported
+            _ => Err(StatusCode::BadDecodingError),
+        }
+    }
+
+    fn decode_value<S: Read>(
+        &self,
+        stream: &mut S,
+        kind: &Kind,
+        depth: usize,
+    ) -> Result<Value, StatusCode> {
+        match kind {
+            Kind::Enumeration => Ok(i32::decode(stream, &DecodingOptions::default())?.into()),
+            Kind::Structure(data_type) => self.decode_structure(stream, data_type, depth + 1),
+            Kind::BuiltIn(id) => match decode_built_in(stream, *id)? {
+                // nested structures may be known too
+                Variant::ExtensionObject(object) => self
+                    .decode_object(&object, depth + 1)
+                    .or_else(|_| Ok(Variant::ExtensionObject(object).to_json())),
+                value => Ok(value.to_json()),
+            },
+        }
+    }
+
+    fn encode_object(
+        &self,
+        data_type: &NodeId,
+        value: &Value,
+    ) -> Result<ExtensionObject, StatusCode> {
+        let encoding_id = self
+            .encodings
+            .iter()
+            .find(|(_, candidate)| *candidate == data_type)
+            .map(|(encoding_id, _)| encoding_id.clone())
+            .ok_or(StatusCode::BadDataTypeIdUnknown)?;
+
+        let mut body = Vec::new();
+        self.encode_structure(&mut body, data_type, value, 0)?;
+
+        Ok(ExtensionObject {
+            node_id: encoding_id,
+            body: ExtensionObjectEncoding::ByteString(ByteString::from(body)),
+        })
+    }
+
+    fn encode_structure<S: Write>(
+        &self,
+        stream: &mut S,
+        data_type: &NodeId,
+        value: &Value,
+        depth: usize,
+    ) -> Result<(), StatusCode> {
+        if depth > MAX_DEPTH {
+            return Err(StatusCode::BadEncodingLimitsExceeded);
+        }
+
+        let structure = self
+            .structures
+            .get(data_type)
+            .ok_or(StatusCode::BadDataTypeIdUnknown)?;
+        let values = value.as_object().ok_or(StatusCode::BadTypeMismatch)?;
+        let get = |field: &Field| values.get(&field.name).filter(|value| !value.is_null());
+
+        match structure.structure_type {
+            StructureType::Structure => {
+                for field in &structure.fields {
+                    let value = get(field).unwrap_or(&Value::Null);
+                    self.encode_field(stream, field, value, depth)?;
+                }
+            }
+            StructureType::StructureWithOptionalFields => {
+                let mut mask = 0u32;
+                for (i, field) in structure
+                    .fields
+                    .iter()
+                    .filter(|field| field.is_optional)
+                    .enumerate()
+                {
+                    if get(field).is_some() {
+                        if i >= 32 {
+                            return Err(StatusCode::BadEncodingError);
+                        }
+                        mask |= 1 << i;
+                    }
+                }
+                mask.encode(stream)?;
+
+                for field in &structure.fields {
+                    match get(field) {
+                        Some(value) => self.encode_field(stream, field, value, depth)?,
+                        None if field.is_optional => {}
+                        None => self.encode_field(stream, field, &Value::Null, depth)?,
+                    }
+                }
+            }
+            StructureType::Union => {
+                match structure
+                    .fields
+                    .iter()
+                    .enumerate()
+                    .find_map(|(i, field)| get(field).map(|value| (i, field, value)))
+                {
+                    Some((i, field, value)) => {
+                        (i as u32 + 1).encode(stream)?;
+                        self.encode_field(stream, field, value, depth)?;
+                    }
+                    None => {
+                        0u32.encode(stream)?;
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn encode_field<S: Write>(
+        &self,
+        stream: &mut S,
+        field: &Field,
+        value: &Value,
+        depth: usize,
+    ) -> Result<(), StatusCode> {
+        match (field.value_rank, value) {
+            (-1, value) => self.encode_value(stream, &field.kind, value, depth),
+            (1, Value::Null) => (-1i32).encode(stream).map(|_| ()),
+            (1, Value::Array(values)) => {
+                (values.len() as i32).encode(stream)?;
+                for value in values {
+                    self.encode_value(stream, &field.kind, value, depth)?;
+                }
+                Ok(())
+            }
+            _ => Err(StatusCode::BadTypeMismatch),
+        }
+    }
+
+    fn encode_value<S: Write>(
+        &self,
+        stream: &mut S,
+        kind: &Kind,
+        value: &Value,
+        depth: usize,
+    ) -> Result<(), StatusCode> {
+        match kind {
+            Kind::Enumeration => {
+                let value = value
+                    .as_i64()
+                    .and_then(|value| i32::try_from(value).ok())
+                    .ok_or(StatusCode::BadTypeMismatch)?;
+                value.encode(stream).map(|_| ())
+            }
+            Kind::Structure(data_type) => {
+                self.encode_structure(stream, data_type, value, depth + 1)
+            }
+            Kind::BuiltIn(EXTENSION_OBJECT) if value.is_object() => {
+                // the data type of the value is unknown, so it must be tagged
+                encode_built_in(stream, EXTENSION_OBJECT, value.clone().into_variant())
+            }
+            Kind::BuiltIn(id) => {
+                let variant = match variant_type(*id) {
+                    Some(variant_type) => coerce::coerce(
+                        value.clone(),
+                        &TypeInfo {
+                            variant_type: Some(variant_type),
+                            value_rank: -1,
+                            data_type: None,
+                        },
+                    )?,
+                    None => value.clone().into_variant(),
+                };
+                encode_built_in(stream, *id, variant)
+            }
+        }
+    }
+}
+
+/// Get the kind of a data type, if it is a built-in type, or an abstract type of those.
+fn built_in(data_type: &NodeId) -> Option<Kind> {
+    match (data_type.namespace, &data_type.identifier) {
+        (0, Identifier::Numeric(id)) if (1..=MAX_BUILT_IN_TYPE).contains(id) => {
+            Some(Kind::BuiltIn(*id))
+        }
+        // Number, Integer, and UInteger are encoded as variant
+        (0, Identifier::Numeric(26..=28)) => Some(Kind::BuiltIn(VARIANT)),
+        // Enumeration
+        (0, Identifier::Numeric(29)) => Some(Kind::Enumeration),
+        _ => None,
+    }
+}
+
+/// Get the variant type of a built-in type, which can be coerced from JSON.
+fn variant_type(id: u32) -> Option<VariantTypeId> {
+    Some(match id {
+        1 => VariantTypeId::Boolean,
+        2 => VariantTypeId::SByte,
+        3 => VariantTypeId::Byte,
+        4 => VariantTypeId::Int16,
+        5 => VariantTypeId::UInt16,
+        6 => VariantTypeId::Int32,
+        7 => VariantTypeId::UInt32,
+        8 => VariantTypeId::Int64,
+        9 => VariantTypeId::UInt64,
+        10 => VariantTypeId::Float,
+        11 => VariantTypeId::Double,
+        12 => VariantTypeId::String,
+        13 => VariantTypeId::DateTime,
+        14 => VariantTypeId::Guid,
+        15 => VariantTypeId::ByteString,
+        17 => VariantTypeId::NodeId,
+        21 => VariantTypeId::LocalizedText,
+        _ => return None,
+    })
+}
+
+/// Decode a value of a built-in type.
+fn decode_built_in<S: Read>(stream: &mut S, id: u32) -> Result<Variant, StatusCode> {
+    let options = &DecodingOptions::default();
+    Ok(match id {
+        1 => Variant::Boolean(bool::decode(stream, options)?),
+        2 => Variant::SByte(i8::decode(stream, options)?),
+        3 => Variant::Byte(u8::decode(stream, options)?),
+        4 => Variant::Int16(i16::decode(stream, options)?),
+        5 => Variant::UInt16(u16::decode(stream, options)?),
+        6 => Variant::Int32(i32::decode(stream, options)?),
+        7 => Variant::UInt32(u32::decode(stream, options)?),
+        8 => Variant::Int64(i64::decode(stream, options)?),
+        9 => Variant::UInt64(u64::decode(stream, options)?),
+        10 => Variant::Float(f32::decode(stream, options)?),
+        11 => Variant::Double(f64::decode(stream, options)?),
+        12 => Variant::String(UAString::decode(stream, options)?),
+        13 => Variant::from(DateTime::decode(stream, options)?),
+        14 => Variant::from(Guid::decode(stream, options)?),
+        15 => Variant::ByteString(ByteString::decode(stream, options)?),
+        16 => Variant::XmlElement(XmlElement::decode(stream, options)?),
+        17 => Variant::from(NodeId::decode(stream, options)?),
+        18 => Variant::ExpandedNodeId(Box::new(ExpandedNodeId::decode(stream, options)?)),
+        19 => Variant::StatusCode(StatusCode::decode(stream, options)?),
+        20 => Variant::QualifiedName(Box::new(QualifiedName::decode(stream, options)?)),
+        21 => Variant::from(LocalizedText::decode(stream, options)?),
+        22 => Variant::ExtensionObject(Box::new(ExtensionObject::decode(stream, options)?)),
+        23 => Variant::DataValue(Box::new(DataValue::decode(stream, options)?)),
+        24 => Variant::decode(stream, options)?,
+        25 => Variant::Diagnostics(Box::new(DiagnosticInfo::decode(stream, options)?)),
+        _ => return Err(StatusCode::BadDecodingError),
+    })
+}
+
+/// Encode a value of a built-in type, which must match the variant.
+fn encode_built_in<S: Write>(stream: &mut S, id: u32, value: Variant) -> Result<(), StatusCode> {
+    let result = match (id, value) {
+        (VARIANT, value) => value.encode(stream),
+        (1, Variant::Boolean(value)) => value.encode(stream),
+        (2, Variant::SByte(value)) => value.encode(stream),
+        (3, Variant::Byte(value)) => value.encode(stream),
+        (4, Variant::Int16(value)) => value.encode(stream),
+        (5, Variant::UInt16(value)) => value.encode(stream),
+        (6, Variant::Int32(value)) => value.encode(stream),
+        (7, Variant::UInt32(value)) => value.encode(stream),
+        (8, Variant::Int64(value)) => value.encode(stream),
+        (9, Variant::UInt64(value)) => value.encode(stream),
+        (10, Variant::Float(value)) => value.encode(stream),
+        (11, Variant::Double(value)) => value.encode(stream),
+        (12, Variant::String(value)) => value.encode(stream),
+        (12, Variant::Empty) => UAString::null().encode(stream),
+        (13, Variant::DateTime(value)) => value.encode(stream),
+        (14, Variant::Guid(value)) => value.encode(stream),
+        (15, Variant::ByteString(value)) => value.encode(stream),
+        (15, Variant::Empty) => ByteString::null().encode(stream),
+        (16, Variant::XmlElement(value)) => value.encode(stream),
+        (17, Variant::NodeId(value)) => value.encode(stream),
+        (18, Variant::ExpandedNodeId(value)) => value.encode(stream),
+        (19, Variant::StatusCode(value)) => value.encode(stream),
+        (20, Variant::QualifiedName(value)) => value.encode(stream),
+        (21, Variant::LocalizedText(value)) => value.encode(stream),
+        (EXTENSION_OBJECT, Variant::ExtensionObject(value)) => value.encode(stream),
+        (EXTENSION_OBJECT, Variant::Empty) => ExtensionObject::null().encode(stream),
+        (23, Variant::DataValue(value)) => value.encode(stream),
+        (25, Variant::Diagnostics(value)) => value.encode(stream),
+        _ => return Err(StatusCode::BadTypeMismatch),
+    };
+
+    result.map(|_| ())
+}
+
+/// Read the data type definition of a data type.
+///
+/// Returns `None` if the data type has no definition, as it isn't a structure or enumeration,
+/// or the server doesn't support the attribute.
+fn read_definition(
+    session: &Session,
+    data_type: &NodeId,
+) -> Result<Option<Definition>, StatusCode> {
+    let value = session
+        .read(
+            &[ReadValueId {
+                node_id: data_type.clone(),
+                attribute_id: AttributeId::DataTypeDefinition as u32,
+                index_range: UAString::null(),
+                data_encoding: QualifiedName::null(),
+            }],
+            TimestampsToReturn::Neither,
+            0.0,
+        )?
+        .into_iter()
+        .next();
+
+    let object = match value.and_then(|value| value.value) {
+        Some(Variant::ExtensionObject(object)) => object,
+        _ => return Ok(None),
+    };
+
+    if object.node_id == NodeId::from(ObjectId::StructureDefinition_Encoding_DefaultBinary) {
+        let definition = object.decode_inner::<StructureDefinition>(&DecodingOptions::default())?;
+        Ok(Some(Definition::Structure(definition)))
+    } else if object.node_id == NodeId::from(ObjectId::EnumDefinition_Encoding_DefaultBinary) {
+        Ok(Some(Definition::Enumeration))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Find the super type of a data type.
+fn super_type(session: &Session, data_type: &NodeId) -> Result<NodeId, StatusCode> {
+    browse_first(
+        session,
+        data_type,
+        BrowseDirection::Inverse,
+        ReferenceTypeId::HasSubtype,
+        |_| true,
+    )
+}
+
+/// Find the data type of a structure in a type dictionary, by its name.
+///
+/// Descriptions are named like the structure, and are referenced by its binary encoding.
+fn legacy_data_type(
+    session: &Session,
+    dictionary_id: &NodeId,
+    name: &str,
+) -> Result<NodeId, StatusCode> {
+    let description = browse_first(
+        session,
+        dictionary_id,
+        BrowseDirection::Forward,
+        ReferenceTypeId::HasComponent,
+        |reference| reference.browse_name.name.as_ref() == name,
+    )?;
+    let encoding_id = browse_first(
+        session,
+        &description,
+        BrowseDirection::Inverse,
+        ReferenceTypeId::HasDescription,
+        |_| true,
+    )?;
+    browse_first(
+        session,
+        &encoding_id,
+        BrowseDirection::Inverse,
+        ReferenceTypeId::HasEncoding,
+        |_| true,
+    )
+}
+
+/// Find the default binary encoding of a data type.
+fn default_binary_encoding(session: &Session, data_type: &NodeId) -> Result<NodeId, StatusCode> {
+    browse_first(
+        session,
+        data_type,
+        BrowseDirection::Forward,
+        ReferenceTypeId::HasEncoding,
+        |reference| reference.browse_name.name.as_ref() == "Default Binary",
+    )
+}
+
+fn browse_first<F>(
+    session: &Session,
+    node_id: &NodeId,
+    browse_direction: BrowseDirection,
+    reference_type: ReferenceTypeId,
+    f: F,
+) -> Result<NodeId, StatusCode>
+where
+    F: Fn(&ReferenceDescription) -> bool,
+{
+    let description = BrowseDescription {
+        node_id: node_id.clone(),
+        browse_direction,
+        reference_type_id: reference_type.into(),
+        include_subtypes: false,
+        node_class_mask: 0,
+        result_mask: BrowseResultMask::All as u32,
+    };
+
+    session
+        .browse(&[description])?
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|result| result.status_code.is_good())
+        .flat_map(|result| result.references.unwrap_or_default())
+        .find(|reference| f(reference))
+        .map(|reference| reference.node_id.node_id)
+        .ok_or(StatusCode::BadDataTypeIdUnknown)
+}
+
+/// Read the output arguments of a method, if it has any.
+fn output_arguments(session: &Session, method_id: &NodeId) -> Result<Vec<Argument>, StatusCode> {
+    let property = match services::find_property(
+        session,
+        method_id,
+        QualifiedName::new(0, "OutputArguments"),
+    )? {
+        Some(property) => property,
+        None => return Ok(vec![]),
+    };
+
+    match services::read_value(session, &property)? {
+        Some(Variant::Array(array)) => array
+            .values
+            .iter()
+            .filter_map(|value| match value {
+                Variant::ExtensionObject(object) => {
+                    Some(object.decode_inner::<Argument>(&DecodingOptions::default()))
+                }
+                _ => None,
+            })
+            .collect(),
+        _ => Ok(vec![]),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use serde_json::json;
+
+    fn field(name: &str, kind: Kind) -> Field {
+        Field {
+            name: name.to_string(),
+            kind,
+            value_rank: -1,
+            is_optional: false,
+        }
+    }
+
+    /// A registry with a nested structure, one with optional fields, and a union.
+    fn registry() -> Registry {
+        let mut registry = Registry::default();
+
+        let mut add = |id: u32, structure_type: StructureType, fields: Vec<Field>| {
+            registry
+                .encodings
+                .insert(NodeId::new(2, id + 1000), NodeId::new(2, id));
+            registry.structures.insert(
+                NodeId::new(2, id),
+                Structure {
+                    structure_type,
+                    fields,
+                },
+            );
+        };
+
+        add(
+            1,
+            StructureType::Structure,
+            vec![
+                field("speed", Kind::BuiltIn(11)),
+                field("name", Kind::BuiltIn(12)),
+                field("mode", Kind::Enumeration),
+                Field {
+                    value_rank: 1,
+                    ..field("values", Kind::BuiltIn(4))
+                },
+                field("limits", Kind::Structure(NodeId::new(2, 2))),
+            ],
+        );
+        add(
+            2,
+            StructureType::StructureWithOptionalFields,
+            vec![
+                Field {
+                    is_optional: true,
+                    ..field("low", Kind::BuiltIn(11))
+                },
+                Field {
+                    is_optional: true,
+                    ..field("high", Kind::BuiltIn(11))
+                },
+            ],
+        );
+        add(
+            3,
+            StructureType::Union,
+            vec![
+                field("number", Kind::BuiltIn(6)),
+                field("text", Kind::BuiltIn(12)),
+            ],
+        );
+
+        registry
+    }
+
+    fn roundtrip(registry: &Registry, id: u32, value: Value) -> Value {
+        let object = registry.encode_object(&NodeId::new(2, id), &value).unwrap();
+        assert_eq!(object.node_id, NodeId::new(2, id + 1000));
+        registry.decode_object(&object, 0).unwrap()
+    }
+
+    #[test]
+    fn test_structure() {
+        let registry = registry();
+        let value = json!({
+            "speed": 1.5,
+            "name": "Pump3",
+            "mode": 2,
+            "values": [1, 2, 3],
+            "limits": {"high": 100.0},
+        });
+        assert_eq!(roundtrip(&registry, 1, value.clone()), value);
+    }
+
+    #[test]
+    fn test_structure_nulls() {
+        let registry = registry();
+        assert_eq!(
+            roundtrip(
+                &registry,
+                1,
+                json!({
+                    "speed": 0.0,
+                    "mode": 0,
+                    "limits": {},
+                })
+            ),
+            json!({
+                "speed": 0.0,
+                "name": "",
+                "mode": 0,
+                "values": null,
+                "limits": {},
+            })
+        );
+    }
+
+    #[test]
+    fn test_union() {
+        let registry = registry();
+        assert_eq!(
+            roundtrip(&registry, 3, json!({"text": "foo"})),
+            json!({"text": "foo"})
+        );
+        assert_eq!(roundtrip(&registry, 3, json!({})), json!({}));
+    }
+
+    #[test]
+    fn test_optional_fields_limit() {
+        let mut registry = Registry::default();
+        registry
+            .encodings
+            .insert(NodeId::new(2, 1004), NodeId::new(2, 4));
+        registry.structures.insert(
+            NodeId::new(2, 4),
+            Structure {
+                structure_type: StructureType::StructureWithOptionalFields,
+                fields: (0..33)
+                    .map(|i| Field {
+                        is_optional: true,
+                        ..field(&format!("f{i}"), Kind::BuiltIn(6))
+                    })
+                    .collect(),
+            },
+        );
+
+        // more optional fields than bits of the mask
+        assert_eq!(
+            registry.encode_object(&NodeId::new(2, 4), &json!({"f32": 1})),
+            Err(StatusCode::BadEncodingError)
+        );
+        let object = ExtensionObject {
+            node_id: NodeId::new(2, 1004),
+            body: ExtensionObjectEncoding::ByteString(ByteString::from(vec![0; 4])),
+        };
+        assert_eq!(
+            registry.decode_object(&object, 0),
+            Err(StatusCode::BadDecodingError)
+        );
+    }
+
+    #[test]
+    fn test_type_mismatch() {
+        let registry = registry();
+        assert_eq!(
+            registry.encode_object(&NodeId::new(2, 1), &json!({"speed": "fast"})),
+            Err(StatusCode::BadTypeMismatch)
+        );
+        assert_eq!(
+            registry.encode_object(&NodeId::new(2, 1), &json!(42)),
+            Err(StatusCode::BadTypeMismatch)
+        );
+    }
+
+    #[test]
+    fn test_built_in() {
+        assert_eq!(
+            built_in(&DataTypeId::Double.into()),
+            Some(Kind::BuiltIn(11))
+        );
+        assert_eq!(
+            built_in(&DataTypeId::Number.into()),
+            Some(Kind::BuiltIn(VARIANT))
+        );
+        assert_eq!(
+            built_in(&DataTypeId::Enumeration.into()),
+            Some(Kind::Enumeration)
+        );
+        assert_eq!(built_in(&DataTypeId::Duration.into()), None);
+        assert_eq!(built_in(&NodeId::new(2, 11)), None);
+    }
+
+    #[test]
+    fn test_variant_type() {
+        assert_eq!(
+            Kind::BuiltIn(DataTypeId::Int16 as u32).variant_type(),
+            Some(VariantTypeId::Int16)
+        );
+        assert_eq!(Kind::Enumeration.variant_type(), Some(VariantTypeId::Int32));
+        assert_eq!(Kind::BuiltIn(VARIANT).variant_type(), None);
+        assert_eq!(Kind::Structure(NodeId::new(2, 1234)).variant_type(), None);
+    }
+}